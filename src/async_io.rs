@@ -0,0 +1,214 @@
+//! Asynchronous checks built on [`tokio`].
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::future::{join_all, FutureExt};
+use futures_util::stream::{self, Stream, StreamExt};
+use tokio::net::{lookup_host, TcpListener, TcpStream};
+use tokio::time::Instant;
+
+use crate::Reachability;
+
+/// Attempts a TCP connection to each port of a host, with up to `concurrency` pending connections,
+/// yielding the outcome of each port as soon as it is known.
+///
+/// This is the asynchronous counterpart of [`crate::scan_ports`]: the host is resolved once and,
+/// if it cannot be resolved, all ports are reported as unreachable.
+/// Results are yielded in completion order, not in port order. No more than `concurrency`
+/// connections are started ahead of the consumer, so a slow consumer slows down the scan.
+pub fn scan_ports_stream<P: IntoIterator<Item = u16>>(
+    host: &str,
+    ports: P,
+    timeout: Duration,
+    concurrency: usize,
+) -> impl Stream<Item = (u16, Reachability)> {
+    let host = host.to_owned();
+    let ips = async move {
+        let ips: Vec<SocketAddr> = match lookup_host((host.as_str(), 0)).await {
+            Ok(addrs) => addrs.collect(),
+            Err(_err) => Vec::new(),
+        };
+        Arc::new(ips)
+    }
+    .boxed()
+    .shared();
+
+    stream::iter(ports)
+        .map(move |port| {
+            let ips = ips.clone();
+            async move { (port, probe(&ips.await, port, timeout).await) }
+        })
+        .buffer_unordered(concurrency.max(1))
+}
+
+/// Binds an asynchronous listener to an ephemeral port of the IPv4 localhost, returning the port
+/// chosen by the OS together with the listener keeping it reserved, ready to accept connections.
+///
+/// This is the asynchronous counterpart of [`crate::reserve_free_port_on`]: the port is never
+/// released between finding it and serving on it, so nobody else can take it in the meantime.
+pub async fn reserve_ephemeral_async() -> io::Result<(u16, TcpListener)> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+    let port = listener.local_addr()?.port();
+    Ok((port, listener))
+}
+
+/// The initial delay between two attempts to reach a target in [`wait_until_all_reachable_async`]
+const INITIAL_DELAY: Duration = Duration::from_millis(10);
+/// The maximum delay between two attempts to reach a target in [`wait_until_all_reachable_async`]
+const MAX_DELAY: Duration = Duration::from_secs(1);
+
+/// Waits until a TCP connection to every target succeeds or the timeout elapses.
+///
+/// This is the asynchronous counterpart of [`crate::wait_until_all_reachable`]: the timeout is a
+/// single deadline for all the targets, which are waited for concurrently within the calling task,
+/// each one retried with an exponential backoff from 10 milliseconds up to 1 second.
+/// On timeout, the targets that are still not reachable are returned, in the original order.
+pub async fn wait_until_all_reachable_async(
+    targets: &[(String, u16)],
+    timeout: Duration,
+) -> Result<(), Vec<(String, u16)>> {
+    // A timeout too large to be added to the current time gives no deadline
+    let deadline = Instant::now().checked_add(timeout);
+    let reachable = join_all(
+        targets
+            .iter()
+            .map(|(host, port)| wait_until_reachable(host, *port, deadline)),
+    )
+    .await;
+    let down: Vec<(String, u16)> = targets
+        .iter()
+        .zip(reachable)
+        .filter(|(_target, reachable)| !reachable)
+        .map(|(target, _reachable)| target.clone())
+        .collect();
+    if down.is_empty() {
+        Ok(())
+    } else {
+        Err(down)
+    }
+}
+
+/// Retries a TCP connection to a host until it succeeds or the deadline passes.
+/// The host is resolved again at each attempt.
+async fn wait_until_reachable(host: &str, port: u16, deadline: Option<Instant>) -> bool {
+    let mut delay = INITIAL_DELAY;
+    loop {
+        let attempt = async {
+            for address in lookup_host((host, port)).await? {
+                if TcpStream::connect(address).await.is_ok() {
+                    return Ok(true);
+                }
+            }
+            Ok::<_, std::io::Error>(false)
+        };
+        let reachable = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, attempt).await.ok(),
+            None => Some(attempt.await),
+        };
+        if let Some(Ok(true)) = reachable {
+            return true;
+        }
+        let remaining = deadline.map_or(Duration::MAX, |deadline| {
+            deadline.saturating_duration_since(Instant::now())
+        });
+        if remaining.is_zero() {
+            return false;
+        }
+        tokio::time::sleep(delay.min(remaining)).await;
+        delay = (delay * 2).min(MAX_DELAY);
+    }
+}
+
+/// Attempts a TCP connection to a port of each address in order, returning the outcome of the last attempt
+async fn probe(ips: &[SocketAddr], port: u16, timeout: Duration) -> Reachability {
+    let mut outcome = Reachability::Unreachable;
+    for ip in ips {
+        // Only the port is replaced, so the scope id of link-local IPv6 addresses is kept
+        let mut address = *ip;
+        address.set_port(port);
+        outcome = match tokio::time::timeout(timeout, TcpStream::connect(address)).await {
+            Ok(Ok(_stream)) => return Reachability::Open,
+            Ok(Err(err)) => Reachability::from_error(&err),
+            Err(_elapsed) => Reachability::TimedOut,
+        };
+    }
+    outcome
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serial_test::serial;
+    use std::collections::BTreeMap;
+
+    #[tokio::test]
+    #[serial]
+    async fn should_stream_the_outcome_of_each_port() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open_port = listener.local_addr().unwrap().port();
+        let closed_port = crate::free_local_ipv4_port().unwrap();
+
+        let results: BTreeMap<u16, Reachability> = scan_ports_stream(
+            "127.0.0.1",
+            [open_port, closed_port],
+            Duration::from_millis(100),
+            2,
+        )
+        .collect()
+        .await;
+
+        assert_eq!(2, results.len());
+        assert_eq!(Some(&Reachability::Open), results.get(&open_port));
+        assert_eq!(Some(&Reachability::Refused), results.get(&closed_port));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn should_wait_for_all_the_targets_within_a_single_deadline() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let up = (
+            "127.0.0.1".to_string(),
+            listener.local_addr().unwrap().port(),
+        );
+        let down = (
+            "127.0.0.1".to_string(),
+            crate::free_local_ipv4_port().unwrap(),
+        );
+        let timeout = Duration::from_millis(200);
+
+        let start = std::time::Instant::now();
+        let targets = [down.clone(), up.clone(), down.clone()];
+        assert_eq!(
+            Err(vec![down.clone(), down]),
+            wait_until_all_reachable_async(&targets, timeout).await
+        );
+        assert!(start.elapsed() < 2 * timeout);
+
+        let up = [up];
+        assert_eq!(Ok(()), wait_until_all_reachable_async(&up, timeout).await);
+        // A timeout too large for a deadline does not panic
+        assert_eq!(
+            Ok(()),
+            wait_until_all_reachable_async(&up, Duration::MAX).await
+        );
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn a_reserved_async_listener_should_accept_connections() {
+        let (port, listener) = reserve_ephemeral_async().await.unwrap();
+        assert_eq!(port, listener.local_addr().unwrap().port());
+        assert!(!crate::is_local_ipv4_port_free(port));
+
+        let (accepted, connected) = tokio::join!(
+            listener.accept(),
+            TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+        );
+        let (_server, client_addr) = accepted.unwrap();
+        assert_eq!(client_addr, connected.unwrap().local_addr().unwrap());
+    }
+}
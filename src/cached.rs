@@ -0,0 +1,121 @@
+//! Reachability checks cached for a while, for endpoints polled many times per second.
+
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::reachability::connect_any;
+use crate::Reachability;
+
+/// A reachability check of a single endpoint whose outcome is reused until it is older than a TTL,
+/// so hot-polled endpoints are not probed at every call.
+///
+/// The checker can be shared between threads: the cache is protected by a mutex held during
+/// the probe, so concurrent calls made while the outcome is stale wait for a single probe
+/// instead of each making its own.
+///
+/// The tradeoff is staleness: a change of the endpoint is only noticed once the cached outcome
+/// expires, so a longer TTL means fewer probes but a slower reaction.
+#[derive(Debug)]
+pub struct CachedChecker {
+    addrs: Vec<SocketAddr>,
+    timeout: Duration,
+    ttl: Duration,
+    cached: Mutex<Option<(Instant, Reachability)>>,
+}
+
+impl CachedChecker {
+    /// Creates a checker for an address with a timeout for each connection attempt and the time
+    /// during which an outcome is reused. The address is resolved once, here.
+    /// No connection is made until the first check.
+    pub fn new<A: ToSocketAddrs>(address: A, timeout: Duration, ttl: Duration) -> io::Result<Self> {
+        let addrs: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+        if addrs.is_empty() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        Ok(CachedChecker {
+            addrs,
+            timeout,
+            ttl,
+            cached: Mutex::new(None),
+        })
+    }
+
+    /// Returns the reachability of the endpoint, probing it only if the cached outcome expired
+    pub fn check(&self) -> Reachability {
+        let mut cached = self
+            .cached
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some((checked_at, reachability)) = *cached {
+            if checked_at.elapsed() < self.ttl {
+                return reachability;
+            }
+        }
+        let reachability = match connect_any(self.addrs.iter().copied(), self.timeout) {
+            Ok(_connected) => Reachability::Open,
+            Err(reachability) => reachability,
+        };
+        *cached = Some((Instant::now(), reachability));
+        reachability
+    }
+
+    /// Returns whether the endpoint is reachable, see [`CachedChecker::check`]
+    pub fn is_reachable(&self) -> bool {
+        self.check().is_open()
+    }
+
+    /// Discards the cached outcome, so the next check probes the endpoint
+    pub fn invalidate(&self) {
+        *self
+            .cached
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serial_test::serial;
+    use std::net::TcpListener;
+
+    #[test]
+    #[serial]
+    fn should_reuse_the_outcome_until_it_expires() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let ttl = Duration::from_millis(200);
+        let checker = CachedChecker::new(
+            listener.local_addr().unwrap(),
+            Duration::from_millis(100),
+            ttl,
+        )
+        .unwrap();
+
+        assert_eq!(Reachability::Open, checker.check());
+        drop(listener);
+        assert!(checker.is_reachable());
+
+        std::thread::sleep(ttl);
+        assert_eq!(Reachability::Refused, checker.check());
+    }
+
+    #[test]
+    #[serial]
+    fn an_invalidated_outcome_should_be_probed_again() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let checker = CachedChecker::new(
+            listener.local_addr().unwrap(),
+            Duration::from_millis(100),
+            Duration::from_secs(60),
+        )
+        .unwrap();
+
+        assert!(checker.is_reachable());
+        drop(listener);
+        checker.invalidate();
+        assert!(!checker.is_reachable());
+    }
+}
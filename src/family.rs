@@ -0,0 +1,184 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener};
+use std::ops::RangeBounds;
+
+use crate::range::inclusive_bounds;
+use crate::{reserve, PortRequest};
+
+/// The IP version of a port check or allocation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Family {
+    /// IPv4 only
+    V4,
+    /// IPv6 only
+    V6,
+    /// Both IPv4 and IPv6 with the same port number (dual-stack)
+    Both,
+}
+
+impl Family {
+    /// Returns the IP version of a socket address.
+    /// IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) are reported as [`Family::V4`].
+    pub fn of(address: &SocketAddr) -> Self {
+        match address {
+            SocketAddr::V4(_) => Family::V4,
+            SocketAddr::V6(address) if address.ip().to_ipv4_mapped().is_some() => Family::V4,
+            SocketAddr::V6(_) => Family::V6,
+        }
+    }
+}
+
+/// Returns whether a port is available on the localhost for the specified IP version.
+/// For [`Family::Both`] the port must be available for both IPv4 and IPv6.
+pub fn is_local_port_free_family(port: u16, family: Family) -> bool {
+    match family {
+        Family::V4 => bind_loopback(Ipv4Addr::LOCALHOST.into(), port).is_some(),
+        Family::V6 => bind_loopback(Ipv6Addr::LOCALHOST.into(), port).is_some(),
+        Family::Both => bind_dual_stack(port).is_some(),
+    }
+}
+
+/// Returns an available localhost port for the specified IP version.
+/// For [`Family::Both`] the port is available for both IPv4 and IPv6; after 64 ephemeral ports
+/// that were not available for IPv6 it gives up and returns `None`.
+pub fn free_local_port_family(family: Family) -> Option<u16> {
+    reserve(PortRequest::Any, family)
+        .ok()
+        .map(|reserved| reserved.port())
+}
+
+/// Returns an available localhost port for the first IP version of `order` that has one,
+/// together with that IP version, e.g. `&[Family::V6, Family::V4]` to prefer IPv6 where available.
+/// Returns `None` if no IP version of the list has an available port.
+pub fn free_local_port_preferring_families(order: &[Family]) -> Option<(u16, Family)> {
+    order
+        .iter()
+        .find_map(|family| free_local_port_family(*family).map(|port| (port, *family)))
+}
+
+/// Returns whether the port of a bound listener is also available on the localhost for the other
+/// IP version, e.g. to check that an IPv4 reservation can be extended to a dual-stack one.
+/// Returns `false` if the address of the listener cannot be read.
+pub fn is_sibling_family_free(listener: &TcpListener) -> bool {
+    match listener.local_addr() {
+        Ok(address) => match Family::of(&address) {
+            Family::V4 => is_local_port_free_family(address.port(), Family::V6),
+            _ => is_local_port_free_family(address.port(), Family::V4),
+        },
+        Err(_) => false,
+    }
+}
+
+/// Returns an available localhost port within the specified range for the specified IP version
+pub fn free_local_port_in_range_family<R: RangeBounds<u16> + Iterator<Item = u16>>(
+    port_range: R,
+    family: Family,
+) -> Option<u16> {
+    // The bounds are u32, so a range including port 65535 cannot overflow;
+    // an empty range starts after 65535 or ends before 0 and fails the conversion
+    let (start, end) = inclusive_bounds(&port_range);
+    let ports = u16::try_from(start).ok()?..=u16::try_from(end).ok()?;
+    reserve(PortRequest::AnyIn(ports), family)
+        .ok()
+        .map(|reserved| reserved.port())
+}
+
+fn bind_loopback(ip: IpAddr, port: u16) -> Option<TcpListener> {
+    TcpListener::bind(SocketAddr::new(ip, port)).ok()
+}
+
+fn bind_dual_stack(port: u16) -> Option<(TcpListener, TcpListener)> {
+    let ipv4 = bind_loopback(Ipv4Addr::LOCALHOST.into(), port)?;
+    let ipv6 = bind_loopback(Ipv6Addr::LOCALHOST.into(), port)?;
+    Some((ipv4, ipv6))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serial_test::serial;
+
+    #[test]
+    fn should_return_the_family_of_an_address() {
+        assert_eq!(Family::V4, Family::of(&"127.0.0.1:80".parse().unwrap()));
+        assert_eq!(Family::V6, Family::of(&"[::1]:80".parse().unwrap()));
+        assert_eq!(
+            Family::V4,
+            Family::of(&"[::ffff:127.0.0.1]:80".parse().unwrap())
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn should_return_a_port_free_for_both_families() {
+        let port = free_local_port_family(Family::Both).unwrap();
+        assert!(is_local_port_free_family(port, Family::V4));
+        assert!(is_local_port_free_family(port, Family::V6));
+        assert!(is_local_port_free_family(port, Family::Both));
+    }
+
+    #[test]
+    #[serial]
+    fn should_return_a_port_of_the_first_family_available() {
+        let (port, family) =
+            free_local_port_preferring_families(&[Family::V6, Family::V4]).unwrap();
+        assert_eq!(Family::V6, family);
+        assert!(is_local_port_free_family(port, Family::V6));
+
+        assert_eq!(
+            Family::V4,
+            free_local_port_preferring_families(&[Family::V4, Family::V6])
+                .unwrap()
+                .1
+        );
+        assert_eq!(None, free_local_port_preferring_families(&[]));
+    }
+
+    #[test]
+    #[serial]
+    fn should_check_the_sibling_family_of_a_listener() {
+        let port = free_local_port_family(Family::Both).unwrap();
+        let ipv4 = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).unwrap();
+        assert!(is_sibling_family_free(&ipv4));
+
+        let ipv6 = TcpListener::bind((Ipv6Addr::LOCALHOST, port)).unwrap();
+        assert!(!is_sibling_family_free(&ipv4));
+        drop(ipv4);
+        assert!(is_sibling_family_free(&ipv6));
+    }
+
+    #[test]
+    #[serial]
+    fn a_port_busy_on_one_family_should_not_be_free_for_both() {
+        let port = free_local_port_family(Family::Both).unwrap();
+        let _listener = TcpListener::bind((Ipv6Addr::LOCALHOST, port)).unwrap();
+
+        assert!(is_local_port_free_family(port, Family::V4));
+        assert!(!is_local_port_free_family(port, Family::V6));
+        assert!(!is_local_port_free_family(port, Family::Both));
+        assert_eq!(
+            None,
+            free_local_port_in_range_family(port..=port, Family::Both)
+        );
+        assert_eq!(
+            Some(port),
+            free_local_port_in_range_family(port..=port, Family::V4)
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn should_search_a_family_range_up_to_the_last_port() {
+        let last = u16::MAX;
+        assert_eq!(
+            is_local_port_free_family(last, Family::V6),
+            free_local_port_in_range_family(last..=last, Family::V6) == Some(last)
+        );
+        let port_found = free_local_port_in_range_family(65530..=last, Family::V4);
+        assert!(port_found.is_none_or(|port| port >= 65530));
+        assert_eq!(
+            None,
+            free_local_port_in_range_family(last..last, Family::V4)
+        );
+    }
+}
@@ -0,0 +1,566 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use crate::reachability::Deadline;
+
+/// The IP version of the first connection attempt of [`connect_happy_eyeballs_with`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FirstFamily {
+    /// IPv4 first
+    V4,
+    /// IPv6 first, as recommended by RFC 8305
+    V6,
+}
+
+impl FirstFamily {
+    fn matches(&self, address: &SocketAddr) -> bool {
+        match self {
+            FirstFamily::V4 => address.is_ipv4(),
+            FirstFamily::V6 => address.is_ipv6(),
+        }
+    }
+
+    fn other(&self) -> Self {
+        match self {
+            FirstFamily::V4 => FirstFamily::V6,
+            FirstFamily::V6 => FirstFamily::V4,
+        }
+    }
+}
+
+/// The tuning of the connection racing of [`connect_happy_eyeballs_with`], as described by RFC 8305.
+/// The default values are the ones recommended by the RFC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HappyEyeballsConfig {
+    /// How long to wait for the addresses of the first family when those of the other family
+    /// are resolved first, 50 ms by default.
+    ///
+    /// The two families are resolved separately on Linux with the `socket2` feature only.
+    /// Elsewhere the system resolver returns the addresses of both families at once,
+    /// so there is never a family to wait for.
+    pub resolution_delay: Duration,
+    /// The delay between the start of two connection attempts, 250 ms by default
+    pub connection_attempt_delay: Duration,
+    /// The family of the first connection attempt, [`FirstFamily::V6`] by default
+    pub first_family: FirstFamily,
+}
+
+impl Default for HappyEyeballsConfig {
+    fn default() -> Self {
+        HappyEyeballsConfig {
+            resolution_delay: Duration::from_millis(50),
+            connection_attempt_delay: Duration::from_millis(250),
+            first_family: FirstFamily::V6,
+        }
+    }
+}
+
+/// Connects to a dual-stack host racing its IPv6 and IPv4 addresses as described by RFC 8305
+/// ("Happy Eyeballs") and returns the first established connection.
+///
+/// Where the two families are resolved separately, the connection attempts start as soon as the
+/// IPv6 addresses are known, or 50 ms after the IPv4 ones if the IPv6 ones are still missing;
+/// addresses resolved later join the race.
+/// Addresses are tried alternating the families, starting with IPv6; a new attempt is started
+/// every 250 ms, or as soon as the previous one fails, without cancelling the pending ones.
+/// See [`connect_happy_eyeballs_with`] to tune these values.
+/// Once a connection is established the pending attempts are cancelled, see [`is_reachable_multishot`].
+/// The timeout bounds the connection attempts, the wait for the first resolved addresses excluded.
+pub fn connect_happy_eyeballs(host: &str, port: u16, timeout: Duration) -> Option<TcpStream> {
+    connect_happy_eyeballs_with(host, port, timeout, &HappyEyeballsConfig::default())
+}
+
+/// Like [`connect_happy_eyeballs`], with the racing tuned by the configuration
+pub fn connect_happy_eyeballs_with(
+    host: &str,
+    port: u16,
+    timeout: Duration,
+    config: &HappyEyeballsConfig,
+) -> Option<TcpStream> {
+    let (sender, receiver) = mpsc::channel();
+    let mut resolving = resolve_families(host, port, &sender);
+
+    // Wait for the first answer, and then for the first family if the other one came first
+    let mut addrs = Vec::new();
+    let mut first_family_resolved = false;
+    let mut wait = None;
+    while resolving > 0 && !first_family_resolved {
+        let event = match wait {
+            None => receiver.recv().ok(),
+            Some(delay) => receiver.recv_timeout(delay).ok(),
+        };
+        let Some(Event::Resolved(family, resolved)) = event else {
+            break;
+        };
+        resolving -= 1;
+        first_family_resolved = family != Some(config.first_family.other());
+        addrs.extend(resolved);
+        wait = Some(config.resolution_delay);
+    }
+
+    let race = Race {
+        queue: interleave_families(addrs, config.first_family).into(),
+        sender,
+        receiver,
+        resolving,
+        attempts: Vec::new(),
+    };
+    race.run(
+        Deadline::after(timeout),
+        config.connection_attempt_delay,
+        config.first_family,
+    )
+}
+
+/// The maximum number of simultaneous connection attempts per address of [`is_reachable_multishot`]
+const MAX_SHOTS: u32 = 8;
+
+/// Starts `shots` simultaneous TCP connection attempts to each socket address the address
+/// resolves to, and returns whether any of them succeeded within the timeout.
+///
+/// On a lossy link a single lost SYN can make a healthy service look down, while it is unlikely
+/// that all the attempts are lost. At most 8 shots are fired per address, a larger number is
+/// capped. As soon as one attempt succeeds the call returns and the others are cancelled.
+/// The attempts are cancelled by shutting down their sockets with the `socket2` feature only;
+/// otherwise they are abandoned and their connections are closed as they complete,
+/// so no socket outlives the timeout.
+pub fn is_reachable_multishot<A: ToSocketAddrs>(address: A, shots: u32, timeout: Duration) -> bool {
+    let Ok(addrs) = address.to_socket_addrs() else {
+        return false;
+    };
+    let shots = shots.min(MAX_SHOTS) as usize;
+    let addrs = addrs
+        .flat_map(|address| std::iter::repeat_n(address, shots))
+        .collect();
+    let (sender, receiver) = mpsc::channel();
+    let race = Race {
+        queue: addrs,
+        sender,
+        receiver,
+        resolving: 0,
+        attempts: Vec::new(),
+    };
+    race.run(Deadline::after(timeout), Duration::ZERO, FirstFamily::V6)
+        .is_some()
+}
+
+/// An event of a connection race
+enum Event {
+    /// The addresses of a family were resolved, of both families for `None`
+    Resolved(Option<FirstFamily>, Vec<SocketAddr>),
+    /// The connection attempt with the index completed
+    Connected(usize, io::Result<TcpStream>),
+}
+
+/// A connection attempt running in the background, which can be cancelled
+#[cfg(feature = "socket2")]
+struct Attempt(Option<socket2::Socket>);
+
+#[cfg(not(feature = "socket2"))]
+struct Attempt;
+
+impl Attempt {
+    /// Starts a connection attempt, sending its outcome as [`Event::Connected`] with the index
+    #[cfg(feature = "socket2")]
+    fn start(
+        index: usize,
+        address: SocketAddr,
+        timeout: Duration,
+        sender: &mpsc::Sender<Event>,
+    ) -> Self {
+        let socket = match socket2::Socket::new(
+            socket2::Domain::for_address(address),
+            socket2::Type::STREAM,
+            None,
+        ) {
+            Ok(socket) => socket,
+            Err(err) => {
+                let _ = sender.send(Event::Connected(index, Err(err)));
+                return Attempt(None);
+            }
+        };
+        // A second handle to the same socket, to shut it down while the attempt is blocked on it
+        let handle = socket.try_clone().ok();
+        let sender = sender.clone();
+        thread::spawn(move || {
+            let connected = socket
+                .connect_timeout(&address.into(), timeout)
+                .map(|()| socket.into());
+            // If the race is already over the receiver is gone and the stream is dropped
+            let _ = sender.send(Event::Connected(index, connected));
+        });
+        Attempt(handle)
+    }
+
+    #[cfg(not(feature = "socket2"))]
+    fn start(
+        index: usize,
+        address: SocketAddr,
+        timeout: Duration,
+        sender: &mpsc::Sender<Event>,
+    ) -> Self {
+        let sender = sender.clone();
+        thread::spawn(move || {
+            let connected = TcpStream::connect_timeout(&address, timeout);
+            // If the race is already over the receiver is gone and the stream is dropped
+            let _ = sender.send(Event::Connected(index, connected));
+        });
+        Attempt
+    }
+
+    /// Makes a pending attempt fail right away, waking up the connection blocked on its socket
+    #[cfg(feature = "socket2")]
+    fn cancel(&self) {
+        if let Some(socket) = &self.0 {
+            let _ = socket.shutdown(std::net::Shutdown::Both);
+        }
+    }
+
+    #[cfg(not(feature = "socket2"))]
+    fn cancel(&self) {}
+}
+
+/// Resolves the addresses of the host in the background, sending them as [`Event::Resolved`],
+/// and returns the number of resolutions started
+#[cfg(all(feature = "socket2", target_os = "linux"))]
+fn resolve_families(host: &str, port: u16, sender: &mpsc::Sender<Event>) -> usize {
+    for family in [FirstFamily::V6, FirstFamily::V4] {
+        let host = host.to_string();
+        let sender = sender.clone();
+        thread::spawn(move || {
+            let addrs = resolve_family(&host, port, family);
+            let _ = sender.send(Event::Resolved(Some(family), addrs));
+        });
+    }
+    2
+}
+
+#[cfg(not(all(feature = "socket2", target_os = "linux")))]
+fn resolve_families(host: &str, port: u16, sender: &mpsc::Sender<Event>) -> usize {
+    let addrs = (host, port)
+        .to_socket_addrs()
+        .map(Iterator::collect)
+        .unwrap_or_default();
+    let _ = sender.send(Event::Resolved(None, addrs));
+    1
+}
+
+/// Resolves the addresses of a single family with `getaddrinfo`, as the standard library
+/// does for both families at once
+#[cfg(all(feature = "socket2", target_os = "linux"))]
+fn resolve_family(host: &str, port: u16, family: FirstFamily) -> Vec<SocketAddr> {
+    use std::ffi::CString;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV6};
+
+    let Ok(host) = CString::new(host) else {
+        return Vec::new();
+    };
+    // SAFETY: addrinfo is a plain C struct for which all zeroes is a valid value
+    let mut hints: libc::addrinfo = unsafe { std::mem::zeroed() };
+    hints.ai_family = match family {
+        FirstFamily::V4 => libc::AF_INET,
+        FirstFamily::V6 => libc::AF_INET6,
+    };
+    hints.ai_socktype = libc::SOCK_STREAM;
+    let mut result: *mut libc::addrinfo = std::ptr::null_mut();
+    // SAFETY: the host is a valid C string, the hints and the result pointer are live
+    let status = unsafe { libc::getaddrinfo(host.as_ptr(), std::ptr::null(), &hints, &mut result) };
+    if status != 0 {
+        return Vec::new();
+    }
+
+    let mut addrs = Vec::new();
+    let mut entry = result;
+    while !entry.is_null() {
+        // SAFETY: the entries of the list returned by getaddrinfo are valid until freeaddrinfo
+        let info = unsafe { &*entry };
+        match info.ai_family {
+            libc::AF_INET => {
+                // SAFETY: an AF_INET entry points to a sockaddr_in
+                let address = unsafe { &*(info.ai_addr as *const libc::sockaddr_in) };
+                let ip = Ipv4Addr::from(u32::from_be(address.sin_addr.s_addr));
+                addrs.push(SocketAddr::from((ip, port)));
+            }
+            libc::AF_INET6 => {
+                // SAFETY: an AF_INET6 entry points to a sockaddr_in6
+                let address = unsafe { &*(info.ai_addr as *const libc::sockaddr_in6) };
+                let ip = Ipv6Addr::from(address.sin6_addr.s6_addr);
+                addrs.push(SocketAddr::V6(SocketAddrV6::new(
+                    ip,
+                    port,
+                    address.sin6_flowinfo,
+                    address.sin6_scope_id,
+                )));
+            }
+            _ => {}
+        }
+        entry = info.ai_next;
+    }
+    // SAFETY: the list was returned by getaddrinfo and is not used afterwards
+    unsafe { libc::freeaddrinfo(result) };
+    addrs
+}
+
+/// Orders the addresses alternating IPv6 and IPv4, starting with the first family
+fn interleave_families(addrs: Vec<SocketAddr>, first_family: FirstFamily) -> Vec<SocketAddr> {
+    let (first, second): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|address| first_family.matches(address));
+    let mut first = first.into_iter();
+    let mut second = second.into_iter();
+    let mut result = Vec::new();
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => return result,
+            (first, second) => result.extend(first.into_iter().chain(second)),
+        }
+    }
+}
+
+/// The connection attempts of a race, and the resolutions still adding addresses to it
+struct Race {
+    /// The addresses not tried yet, in order
+    queue: VecDeque<SocketAddr>,
+    sender: mpsc::Sender<Event>,
+    receiver: mpsc::Receiver<Event>,
+    /// The number of resolutions not completed yet
+    resolving: usize,
+    /// The connection attempts started, by index, `None` once completed
+    attempts: Vec<Option<Attempt>>,
+}
+
+impl Race {
+    /// Starts the connection attempts in order, staggered by the attempt delay, and returns the
+    /// first one that succeeds, cancelling the others. Addresses resolved in the meantime are merged into the queue,
+    /// alternating the families from the one after the last attempt.
+    fn run(
+        mut self,
+        deadline: Deadline,
+        attempt_delay: Duration,
+        mut next_family: FirstFamily,
+    ) -> Option<TcpStream> {
+        let mut next_attempt = Deadline::after(Duration::ZERO);
+        loop {
+            let remaining = deadline.remaining();
+            if remaining.is_zero() {
+                return None;
+            }
+            if next_attempt.remaining().is_zero() {
+                if let Some(address) = self.queue.pop_front() {
+                    let attempt =
+                        Attempt::start(self.attempts.len(), address, remaining, &self.sender);
+                    self.attempts.push(Some(attempt));
+                    next_family = if address.is_ipv6() {
+                        FirstFamily::V4
+                    } else {
+                        FirstFamily::V6
+                    };
+                    next_attempt = Deadline::after(attempt_delay);
+                    continue;
+                }
+            }
+            let pending = self.attempts.iter().any(Option::is_some);
+            if self.queue.is_empty() && !pending && self.resolving == 0 {
+                return None;
+            }
+
+            // Wait for the next attempt, unless an attempt or a resolution completes first
+            let wait = if self.queue.is_empty() {
+                remaining
+            } else {
+                next_attempt.remaining().min(remaining)
+            };
+            match self.receiver.recv_timeout(wait) {
+                Ok(Event::Connected(index, Ok(stream))) => {
+                    self.attempts[index] = None;
+                    for attempt in self.attempts.iter().flatten() {
+                        attempt.cancel();
+                    }
+                    return Some(stream);
+                }
+                Ok(Event::Connected(index, Err(_err))) => {
+                    self.attempts[index] = None;
+                    next_attempt = Deadline::after(Duration::ZERO);
+                }
+                Ok(Event::Resolved(_family, addrs)) => {
+                    self.resolving -= 1;
+                    let queue = self.queue.drain(..).chain(addrs).collect();
+                    self.queue = interleave_families(queue, next_family).into();
+                }
+                Err(_timeout) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serial_test::serial;
+    use std::net::{Ipv4Addr, Ipv6Addr, TcpListener};
+    use std::time::Instant;
+
+    #[test]
+    fn should_interleave_families_starting_with_ipv6() {
+        let v4_1 = SocketAddr::from((Ipv4Addr::new(192, 0, 2, 1), 80));
+        let v4_2 = SocketAddr::from((Ipv4Addr::new(192, 0, 2, 2), 80));
+        let v6_1 = SocketAddr::from((Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1), 80));
+        assert_eq!(
+            vec![v6_1, v4_1, v4_2],
+            interleave_families(vec![v4_1, v4_2, v6_1], FirstFamily::V6)
+        );
+        assert_eq!(
+            vec![v4_1, v6_1, v4_2],
+            interleave_families(vec![v4_1, v4_2, v6_1], FirstFamily::V4)
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn should_connect_to_the_listening_family() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let stream = connect_happy_eyeballs("localhost", port, Duration::from_secs(1)).unwrap();
+        assert_eq!(port, stream.peer_addr().unwrap().port());
+        assert!(stream.peer_addr().unwrap().is_ipv4());
+    }
+
+    #[test]
+    #[serial]
+    fn should_connect_with_a_custom_configuration() {
+        let listener = TcpListener::bind("[::1]:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config = HappyEyeballsConfig {
+            resolution_delay: Duration::from_millis(10),
+            connection_attempt_delay: Duration::from_millis(10),
+            first_family: FirstFamily::V4,
+        };
+
+        let stream =
+            connect_happy_eyeballs_with("::1", port, Duration::from_secs(1), &config).unwrap();
+        assert_eq!(listener.local_addr().unwrap(), stream.peer_addr().unwrap());
+    }
+
+    #[test]
+    #[serial]
+    fn addresses_resolved_during_the_race_should_join_it() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let late = sender.clone();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            let _ = late.send(Event::Resolved(Some(FirstFamily::V4), vec![address]));
+        });
+        let race = Race {
+            queue: VecDeque::new(),
+            sender,
+            receiver,
+            resolving: 1,
+            attempts: Vec::new(),
+        };
+
+        let stream = race
+            .run(
+                Deadline::after(Duration::from_secs(1)),
+                Duration::ZERO,
+                FirstFamily::V6,
+            )
+            .unwrap();
+        assert_eq!(address, stream.peer_addr().unwrap());
+    }
+
+    #[cfg(all(feature = "socket2", target_os = "linux"))]
+    #[test]
+    fn should_resolve_each_family_separately() {
+        let v4 = resolve_family("localhost", 80, FirstFamily::V4);
+        assert!(v4.contains(&SocketAddr::from((Ipv4Addr::LOCALHOST, 80))));
+        assert!(v4.iter().all(SocketAddr::is_ipv4));
+        assert!(resolve_family("localhost", 80, FirstFamily::V6)
+            .iter()
+            .all(SocketAddr::is_ipv6));
+        assert!(resolve_family("127.0.0.1", 80, FirstFamily::V6).is_empty());
+        assert!(resolve_family("missing host\0", 80, FirstFamily::V4).is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn any_shot_should_make_the_address_reachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let timeout = Duration::from_secs(1);
+        assert!(is_reachable_multishot(address, 4, timeout));
+        assert!(!is_reachable_multishot(address, 0, timeout));
+        // The shots are capped, so a huge number does not start as many attempts
+        assert!(is_reachable_multishot(address, u32::MAX, timeout));
+
+        drop(listener);
+        let start = Instant::now();
+        assert!(!is_reachable_multishot(address, 4, timeout));
+        assert!(start.elapsed() < timeout);
+        assert!(!is_reachable_multishot("missing port", 4, timeout));
+    }
+
+    /// Returns a listener that never completes a handshake, its accept queue being full,
+    /// together with the connection filling the queue
+    #[cfg(feature = "socket2")]
+    fn hanging_listener() -> (socket2::Socket, TcpStream) {
+        let listener =
+            socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::STREAM, None).unwrap();
+        listener
+            .bind(&SocketAddr::from((Ipv4Addr::LOCALHOST, 0)).into())
+            .unwrap();
+        listener.listen(0).unwrap();
+        let address = listener.local_addr().unwrap().as_socket().unwrap();
+        let filler = TcpStream::connect(address).unwrap();
+        (listener, filler)
+    }
+
+    #[cfg(feature = "socket2")]
+    #[test]
+    #[serial]
+    fn should_return_before_the_timeout_once_a_shot_succeeds() {
+        let (hanging, _filler) = hanging_listener();
+        let hanging = hanging.local_addr().unwrap().as_socket().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addrs = [hanging, listener.local_addr().unwrap()];
+        let timeout = Duration::from_secs(5);
+
+        let start = Instant::now();
+        assert!(is_reachable_multishot(&addrs[..], 2, timeout));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[cfg(feature = "socket2")]
+    #[test]
+    #[serial]
+    fn a_cancelled_attempt_should_fail_right_away() {
+        let (hanging, _filler) = hanging_listener();
+        let hanging = hanging.local_addr().unwrap().as_socket().unwrap();
+        let (sender, receiver) = mpsc::channel();
+
+        let attempt = Attempt::start(0, hanging, Duration::from_secs(5), &sender);
+        thread::sleep(Duration::from_millis(50));
+        attempt.cancel();
+        match receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(Event::Connected(0, connected)) => assert!(connected.is_err()),
+            _ => panic!("the cancelled attempt did not complete"),
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn should_return_none_if_nothing_is_listening() {
+        let mut port = crate::free_local_ipv4_port().unwrap();
+        while !crate::is_local_ipv6_port_free(port) {
+            port = crate::free_local_ipv4_port().unwrap();
+        }
+        assert!(connect_happy_eyeballs("localhost", port, Duration::from_secs(1)).is_none());
+    }
+}
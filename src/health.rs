@@ -0,0 +1,117 @@
+//! Health checks of established TCP connections, e.g. for connection pools.
+
+use std::io;
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// The health of an established TCP connection, see [`stream_health`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamHealth {
+    /// Whether the connection is still open, see [`is_stream_alive`]
+    pub alive: bool,
+    /// The smoothed round trip time estimated by the kernel.
+    /// Only available on Linux with the `socket2` feature.
+    pub rtt: Option<Duration>,
+}
+
+/// Returns whether an established connection is still open, without consuming any data.
+///
+/// The stream is peeked in non-blocking mode: the connection is dead if the peer closed or
+/// reset it, while pending data or no data at all both mean it is alive. This only detects
+/// what the OS already knows, not a peer that vanished silently.
+/// The stream is then put back in the mode it had before, e.g. a stream driven by an event loop
+/// stays non-blocking. The mode can only be read on Unix platforms with the `socket2` feature;
+/// elsewhere the stream is assumed to be blocking, and is left in blocking mode.
+pub fn is_stream_alive(stream: &TcpStream) -> bool {
+    let was_nonblocking = is_nonblocking(stream);
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let alive = match stream.peek(&mut [0u8; 1]) {
+        Ok(read) => read > 0,
+        Err(err) => err.kind() == io::ErrorKind::WouldBlock,
+    };
+    stream.set_nonblocking(was_nonblocking).is_ok() && alive
+}
+
+#[cfg(all(feature = "socket2", unix))]
+fn is_nonblocking(stream: &TcpStream) -> bool {
+    socket2::SockRef::from(stream)
+        .nonblocking()
+        .unwrap_or(false)
+}
+
+#[cfg(not(all(feature = "socket2", unix)))]
+fn is_nonblocking(_stream: &TcpStream) -> bool {
+    false
+}
+
+/// Returns whether an established connection is still open, as [`is_stream_alive`] does,
+/// together with its round trip time where the platform reports it, so that pools can
+/// evict degraded connections.
+pub fn stream_health(stream: &TcpStream) -> StreamHealth {
+    StreamHealth {
+        alive: is_stream_alive(stream),
+        rtt: rtt(stream),
+    }
+}
+
+#[cfg(feature = "socket2")]
+fn rtt(stream: &TcpStream) -> Option<Duration> {
+    crate::tcp_info::tcp_info(stream).rtt
+}
+
+#[cfg(not(feature = "socket2"))]
+fn rtt(_stream: &TcpStream) -> Option<Duration> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serial_test::serial;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    #[serial]
+    fn a_stream_should_be_alive_until_the_peer_closes_it() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        let health = stream_health(&client);
+        assert!(health.alive);
+        #[cfg(all(feature = "socket2", target_os = "linux"))]
+        assert!(health.rtt.is_some());
+
+        // Pending data is not consumed
+        server.write_all(b"x").unwrap();
+        assert!(is_stream_alive(&client));
+        let mut received = [0u8; 1];
+        client.read_exact(&mut received).unwrap();
+        assert_eq!(*b"x", received);
+
+        drop(server);
+        // The FIN from the peer may take a moment to be processed
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!is_stream_alive(&client));
+    }
+
+    #[cfg(all(feature = "socket2", unix))]
+    #[test]
+    #[serial]
+    fn a_stream_should_be_left_in_its_previous_mode() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (_server, _) = listener.accept().unwrap();
+
+        assert!(is_stream_alive(&client));
+        assert!(!socket2::SockRef::from(&client).nonblocking().unwrap());
+
+        client.set_nonblocking(true).unwrap();
+        assert!(is_stream_alive(&client));
+        assert!(socket2::SockRef::from(&client).nonblocking().unwrap());
+    }
+}
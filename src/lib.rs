@@ -1,17 +1,120 @@
 #![doc = include_str!("../README.md")]
 
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::net::{
-    Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6, TcpListener, TcpStream, ToSocketAddrs,
+    IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, TcpListener, TcpStream,
+    ToSocketAddrs,
 };
-use std::ops::RangeBounds;
-use std::time::Duration;
-
-/// Represents a port for an IP address
+use std::ops::{RangeBounds, RangeInclusive};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "tokio")]
+mod async_io;
+mod cached;
+mod family;
+mod happy_eyeballs;
+mod health;
+mod os;
+#[cfg(feature = "proxy")]
+mod proxy;
+mod range;
+mod reachability;
+mod reserve;
+mod scan;
+#[cfg(all(feature = "sctp", any(target_os = "linux", target_os = "freebsd")))]
+mod sctp;
+#[cfg(feature = "socket2")]
+mod socket;
+mod target;
+#[cfg(feature = "socket2")]
+mod tcp_info;
+mod udp;
+mod wait;
+mod warm_probe;
+mod watch;
+pub mod well_known;
+
+#[cfg(feature = "tokio")]
+pub use async_io::{reserve_ephemeral_async, scan_ports_stream, wait_until_all_reachable_async};
+pub use cached::CachedChecker;
+pub use family::{
+    free_local_port_family, free_local_port_in_range_family, free_local_port_preferring_families,
+    is_local_port_free_family, is_sibling_family_free, Family,
+};
+pub use happy_eyeballs::{
+    connect_happy_eyeballs, connect_happy_eyeballs_with, is_reachable_multishot, FirstFamily,
+    HappyEyeballsConfig,
+};
+pub use health::{is_stream_alive, stream_health, StreamHealth};
+pub use os::{
+    free_local_port_fresh, ipv6_usable, is_local_port_listening, is_port_reserved_by_os,
+    local_listening_ports,
+};
+#[cfg(feature = "proxy")]
+pub use proxy::{
+    is_port_reachable_via_http_proxy, is_port_reachable_via_proxy_chain,
+    is_port_reachable_with_proxy_protocol, ProxyProtocol,
+};
+pub use range::{
+    free_local_port_in_range_with, free_local_port_in_ranges, free_local_port_restricted,
+    free_local_ports_in_range, plan_range, port_for_key, range_bind_diagnostics,
+    try_free_local_port_in_range, RangeError, RangeOptions,
+};
+pub use reachability::{
+    can_resolve, compare_local_vs_external, explain_reachability, first_reachable_addr,
+    first_reachable_addr_with_family, first_reachable_ipv6_addr, is_port_open_externally,
+    is_port_reachable_with_timeouts, is_reachable_with_exchange, is_service_reachable_locally,
+    loopback_reachability, port_reachability, port_reachability_with_family, port_state,
+    time_to_first_byte, ForwardStatus, PortState, Reachability,
+};
+#[cfg(feature = "socket2")]
+pub use reserve::reserve_ephemeral_inheritable;
+#[cfg(feature = "log")]
+pub use reserve::DebugReservedPort;
+pub use reserve::{
+    can_allocate_ephemeral, check_ephemeral_allocation, free_local_ports, free_port_on_each,
+    probe_bindable_capacity, reserve, reserve_and_write, reserve_ephemeral_retrying,
+    reserve_free_port_on, reserve_local_ipv4_port, reserve_local_ipv6_port, reserve_local_port,
+    reserve_pool, reserve_spread_ports, verified_free_local_port, verify_local_port_free,
+    BatchError, PortFile, PortPool, PortRequest, ReservedPort, RollingPort, VerifiedFreePort,
+};
+pub use scan::{
+    first_reachable_port_in_range, first_reachable_target, rank_by_latency, reachability_report,
+    scan_ports, PortChecker, ScanReport, ScanSummary,
+};
+#[cfg(all(feature = "sctp", any(target_os = "linux", target_os = "freebsd")))]
+pub use sctp::is_sctp_port_reachable;
+#[cfg(feature = "socket2")]
+pub use socket::{
+    bind_report, is_local_port_free_configured, is_port_reachable_from_source_port,
+    is_port_reachable_tfo, is_reachable_with_socket, probe_tfo, BindReport, TfoProbe,
+};
+pub use target::{is_target_reachable, parse_target, parse_target_or, ParseError};
+#[cfg(feature = "socket2")]
+pub use tcp_info::{reachable_with_tcp_info, TcpInfo};
+pub use udp::{is_port_reachable_tcp_and_udp, is_udp_peer_alive, is_udp_service_reachable};
+pub use wait::{
+    assert_reachable_within, wait_until_all_reachable, wait_until_local_ipv4_port_busy,
+    wait_until_local_ipv6_port_busy, wait_until_local_port_busy, wait_until_reachable,
+    wait_until_reachable_backoff, wait_until_reachable_secs, WaitOutcome,
+};
+pub use warm_probe::WarmProbe;
+pub use watch::{watch_reachability, WatchHandle};
+
+/// Represents a port for an IP address.
+///
+/// Ports are ordered by their number first and by their IP version second, IPv4 before IPv6
+/// before both, so the same number for all versions sorts next to each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Port {
     /// Represents a port for an IPv4 address
     Ipv4(u16),
     /// Represents a port for an IPv6 address
     Ipv6(u16),
+    /// Represents the same port for both an IPv4 and an IPv6 address (dual-stack):
+    /// it is free only if it is free for both IP versions
+    Both(u16),
 }
 
 impl From<u16> for Port {
@@ -20,6 +123,28 @@ impl From<u16> for Port {
     }
 }
 
+impl From<SocketAddr> for Port {
+    /// Returns the port of the address, for the IP version of the address
+    fn from(address: SocketAddr) -> Self {
+        match address {
+            SocketAddr::V4(address) => address.into(),
+            SocketAddr::V6(address) => address.into(),
+        }
+    }
+}
+
+impl From<SocketAddrV4> for Port {
+    fn from(address: SocketAddrV4) -> Self {
+        Port::Ipv4(address.port())
+    }
+}
+
+impl From<SocketAddrV6> for Port {
+    fn from(address: SocketAddrV6) -> Self {
+        Port::Ipv6(address.port())
+    }
+}
+
 impl Port {
     /// Creates a new IPv4 port with the specified value
     pub fn new(port: u16) -> Self {
@@ -35,6 +160,41 @@ impl Port {
     pub fn ipv6(port: u16) -> Self {
         Port::Ipv6(port)
     }
+
+    /// Creates a new port with the specified value for both IPv4 and IPv6
+    pub fn both(port: u16) -> Self {
+        Port::Both(port)
+    }
+
+    /// Returns the port number
+    pub fn number(&self) -> u16 {
+        match self {
+            Port::Ipv4(port) | Port::Ipv6(port) | Port::Both(port) => *port,
+        }
+    }
+
+    /// The position of the IP version in the ordering
+    fn family_rank(&self) -> u8 {
+        match self {
+            Port::Ipv4(_) => 0,
+            Port::Ipv6(_) => 1,
+            Port::Both(_) => 2,
+        }
+    }
+}
+
+impl Ord for Port {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.number()
+            .cmp(&other.number())
+            .then_with(|| self.family_rank().cmp(&other.family_rank()))
+    }
+}
+
+impl PartialOrd for Port {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
 /// Represents a port range for an IP address
@@ -68,12 +228,22 @@ impl<R: RangeBounds<u16> + std::iter::Iterator<Item = u16>> From<R> for Ports<R>
     }
 }
 
-/// Attempts a TCP connection to an address and returns whether it succeeded
+/// A sensible timeout for a single connection attempt, used by [`is_port_reachable_default`]
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Attempts a TCP connection to an address and returns whether it succeeded.
+///
+/// The connection attempt is bound only by the OS timeout, which can be minutes for a
+/// filtered host; prefer [`is_port_reachable_default`] or [`is_port_reachable_with_timeout`]
+/// when the address may not answer.
 pub fn is_port_reachable<A: ToSocketAddrs>(address: A) -> bool {
     TcpStream::connect(address).is_ok()
 }
 
-/// Attempts a TCP connection to an address and returns whether it succeeded
+/// Attempts a TCP connection to an address and returns whether it succeeded.
+///
+/// The timeout bounds each connection attempt but not the resolution of a host name;
+/// use [`is_port_reachable_with_timeouts`] to bound both.
 pub fn is_port_reachable_with_timeout<A: ToSocketAddrs>(address: A, timeout: Duration) -> bool {
     match address.to_socket_addrs() {
         Ok(addrs) => {
@@ -88,29 +258,110 @@ pub fn is_port_reachable_with_timeout<A: ToSocketAddrs>(address: A, timeout: Dur
     }
 }
 
+/// Attempts a TCP connection to an address with a timeout in seconds and returns whether it succeeded
+pub fn is_port_reachable_secs<A: ToSocketAddrs>(address: A, secs: u64) -> bool {
+    is_port_reachable_with_timeout(address, Duration::from_secs(secs))
+}
+
+/// Attempts a TCP connection to the IPv4 loopback address (`127.0.0.1`) and returns whether it succeeded
+pub fn is_ipv4_loopback_reachable(port: u16, timeout: Duration) -> bool {
+    is_port_reachable_with_timeout((Ipv4Addr::LOCALHOST, port), timeout)
+}
+
+/// Attempts a TCP connection to the IPv6 loopback address (`::1`) and returns whether it succeeded
+pub fn is_ipv6_loopback_reachable(port: u16, timeout: Duration) -> bool {
+    is_port_reachable_with_timeout((Ipv6Addr::LOCALHOST, port), timeout)
+}
+
+/// Attempts a TCP connection to each address in order, each one with its own timeout,
+/// and returns whether any of them succeeded
+pub fn is_reachable_with_addr_timeouts(addrs: &[(SocketAddr, Duration)]) -> bool {
+    addrs
+        .iter()
+        .any(|(address, timeout)| TcpStream::connect_timeout(address, *timeout).is_ok())
+}
+
+/// Attempts a TCP connection to an address with a timeout of [`DEFAULT_TIMEOUT`] and returns whether it succeeded
+pub fn is_port_reachable_default<A: ToSocketAddrs>(address: A) -> bool {
+    is_port_reachable_with_timeout(address, DEFAULT_TIMEOUT)
+}
+
 /// Returns whether a port is available on the localhost
 /// If the IP version is not specified, it defaults to IPv4. This happens when the port is specified as a number.
+/// A [`Port::Both`] must be available for both IPv4 and IPv6.
 pub fn is_local_port_free<P: Into<Port>>(port: P) -> bool {
     match port.into() {
         Port::Ipv4(port) => is_local_ipv4_port_free(port),
         Port::Ipv6(port) => is_local_ipv6_port_free(port),
+        Port::Both(port) => is_local_port_free_family(port, Family::Both),
     }
 }
 
 /// Returns whether a port is available on the localhost for IPv4
 pub fn is_local_ipv4_port_free(port: u16) -> bool {
-    let ipv4 = SocketAddrV4::new(Ipv4Addr::LOCALHOST, port);
-    TcpListener::bind(ipv4).is_ok()
+    is_local_port_free_family(port, Family::V4)
 }
 
 /// Returns whether a port is available on the localhost for IPv6
 pub fn is_local_ipv6_port_free(port: u16) -> bool {
-    let ipv6 = SocketAddrV6::new(Ipv6Addr::LOCALHOST, port, 0, 0);
-    TcpListener::bind(ipv6).is_ok()
+    is_local_port_free_family(port, Family::V6)
+}
+
+/// Returns whether a listener can be bound to exactly the specified IP address and port.
+///
+/// Unlike [`is_local_port_free`], which only checks the loopback address, this attempts the
+/// same bind a server would do, e.g. on a specific interface or on the unspecified address.
+pub fn is_addr_bindable(ip: IpAddr, port: u16) -> bool {
+    TcpListener::bind(SocketAddr::new(ip, port)).is_ok()
+}
+
+/// Returns whether a listener can be bound to the port on each of the specified IP addresses,
+/// e.g. to find out that a port is free on the loopback address but not on a public one.
+///
+/// Each address is tried as [`is_addr_bindable`] does, and released before the next one.
+pub fn bindability_by_ip(ips: &[IpAddr], port: u16) -> HashMap<IpAddr, bool> {
+    ips.iter()
+        .map(|ip| (*ip, is_addr_bindable(*ip, port)))
+        .collect()
+}
+
+/// The timeout of each step of [`verify_bind_and_connect`]
+const SELF_TEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Returns whether a port is usable on the localhost for IPv4: a listener can be bound to it,
+/// a client can connect to it, and a byte sent by the server reaches the client.
+///
+/// Everything is torn down before returning. Unlike [`is_local_port_free`], this also detects
+/// a broken loopback interface, e.g. a misconfigured container network, where bind succeeds
+/// but connections never go through.
+pub fn verify_bind_and_connect(port: u16) -> bool {
+    self_test(port).is_ok()
+}
+
+fn self_test(port: u16) -> std::io::Result<()> {
+    use std::io::{Read, Write};
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+    let address = listener.local_addr()?;
+    // The handshake is completed by the OS, so the connection can be accepted afterwards
+    let mut client = TcpStream::connect_timeout(&address, SELF_TEST_TIMEOUT)?;
+    let (mut server, _) = listener.accept()?;
+    server.write_all(&[42])?;
+    client.set_read_timeout(Some(SELF_TEST_TIMEOUT))?;
+    let mut received = [0u8; 1];
+    client.read_exact(&mut received)?;
+    if received == [42] {
+        Ok(())
+    } else {
+        Err(std::io::ErrorKind::InvalidData.into())
+    }
 }
 
 /// Returns an available localhost port within the specified range.
 /// If the IP version is not specified, it defaults to IPv4. This happens when the port range is specified as a range.
+///
+/// A range whose start is greater than its end, e.g. `5000..3000`, is empty, so `None` is returned
+/// as if no port was available; use [`try_free_local_port_in_range`] to tell the two cases apart.
 pub fn free_local_port_in_range<
     P: Into<Ports<R>>,
     R: RangeBounds<u16> + std::iter::Iterator<Item = u16>,
@@ -127,18 +378,21 @@ pub fn free_local_port_in_range<
 pub fn free_local_ipv4_port_in_range<R: RangeBounds<u16> + std::iter::Iterator<Item = u16>>(
     port_range: R,
 ) -> Option<u16> {
-    port_range
-        .into_iter()
-        .find(|port| is_local_ipv4_port_free(*port))
+    free_local_port_in_range_family(port_range, Family::V4)
 }
 
 /// Returns an available localhost port within the specified range for IPv6.
 pub fn free_local_ipv6_port_in_range<R: RangeBounds<u16> + std::iter::Iterator<Item = u16>>(
     port_range: R,
 ) -> Option<u16> {
-    port_range
-        .into_iter()
-        .find(|port| is_local_ipv6_port_free(*port))
+    free_local_port_in_range_family(port_range, Family::V6)
+}
+
+/// Returns the range of the ports within `span` of `center`, in both directions.
+/// The range is clamped to the valid port numbers instead of overflowing,
+/// so it is safe to use with ports close to 0 or 65535.
+pub fn range_around(center: u16, span: u16) -> RangeInclusive<u16> {
+    center.saturating_sub(span)..=center.saturating_add(span)
 }
 
 /// Returns an available localhost port for IPv4
@@ -148,20 +402,86 @@ pub fn free_local_port() -> Option<u16> {
 
 /// Returns an available localhost port for IPv4
 pub fn free_local_ipv4_port() -> Option<u16> {
-    let socket = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
-    TcpListener::bind(socket)
-        .and_then(|listener| listener.local_addr())
-        .map(|addr| addr.port())
-        .ok()
+    free_local_port_family(Family::V4)
 }
 
 /// Returns an available localhost port for IPv6
 pub fn free_local_ipv6_port() -> Option<u16> {
-    let socket = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0);
-    TcpListener::bind(socket)
-        .and_then(|listener| listener.local_addr())
-        .map(|addr| addr.port())
+    free_local_port_family(Family::V6)
+}
+
+/// Like [`free_local_port`], but also returns how long the allocation took.
+/// Useful to find out whether port allocation is what slows down startup on a loaded machine.
+pub fn free_local_port_timed() -> (Option<u16>, Duration) {
+    let start = Instant::now();
+    let port = free_local_port();
+    (port, start.elapsed())
+}
+
+/// The maximum number of ports tried by [`free_local_port_immediately_reusable`]
+const MAX_REUSABLE_ATTEMPTS: usize = 8;
+
+/// Returns an available localhost port for IPv4 that was confirmed to be bindable again
+/// right after its listener was closed.
+///
+/// A closed listener leaves no TIME_WAIT state behind unless it had accepted connections,
+/// but a port may still be rejected right after being released, e.g. on a machine running out
+/// of ports. On Unix platforms the listeners are bound with `SO_REUSEADDR`, as the standard library
+/// does by default, which lets a server bind a port even if connections to it are still in
+/// TIME_WAIT; on Windows `SO_REUSEADDR` would allow stealing a port in use, so it is never set there
+/// and the confirmation is stricter. Returns `None` after 8 ports that could not be bound again.
+pub fn free_local_port_immediately_reusable() -> Option<u16> {
+    (0..MAX_REUSABLE_ATTEMPTS).find_map(|_| {
+        let (port, listener) = reserve_free_port_on(Ipv4Addr::LOCALHOST.into()).ok()?;
+        drop(listener);
+        TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .ok()
+            .map(|_listener| port)
+    })
+}
+
+/// Returns `preferred` if it is available on the localhost for IPv4, otherwise the next available port above it.
+/// The search stops at port 65535 without wrapping around, returning `None` if no port is available.
+pub fn free_local_port_from_hint(preferred: u16) -> Option<u16> {
+    reserve_local_port_from_hint(preferred).map(|(port, _listener)| port)
+}
+
+/// Like [`free_local_port_from_hint`], but returns the port together with a listener bound to it,
+/// so the port cannot be taken by someone else before it is used.
+pub fn reserve_local_port_from_hint(preferred: u16) -> Option<(u16, TcpListener)> {
+    reserve(PortRequest::AnyIn(preferred..=u16::MAX), Family::V4)
         .ok()
+        .map(|reserved| (reserved.port(), reserved.into_listener()))
+}
+
+/// The first port that can be bound without privileges on most platforms
+const FIRST_UNPRIVILEGED_PORT: u16 = 1024;
+
+/// Returns the lowest available localhost port for IPv4 that does not require privileges to be bound,
+/// i.e. 1024 or above, together with a listener bound to it so it cannot be taken by someone else.
+///
+/// The ports below 1024 are never tried, so processes without privileges do not waste attempts
+/// on them. See [`reserve_local_port_from_hint`].
+pub fn free_local_port_unprivileged() -> Option<(u16, TcpListener)> {
+    reserve_local_port_from_hint(FIRST_UNPRIVILEGED_PORT)
+}
+
+/// The maximum number of ports allocated by [`free_local_port_avoiding`] before giving up
+const MAX_AVOIDING_ATTEMPTS: usize = 64;
+
+/// Returns an available localhost port for IPv4 that is not in the `avoid` list.
+/// Ports handed back by the OS that are in the list are kept bound until a suitable port is found,
+/// so they are not offered again; after 64 unsuitable ports it gives up and returns `None`.
+pub fn free_local_port_avoiding(avoid: &[u16]) -> Option<u16> {
+    let mut rejected = Vec::new();
+    for _ in 0..MAX_AVOIDING_ATTEMPTS {
+        let (port, listener) = reserve_free_port_on(Ipv4Addr::LOCALHOST.into()).ok()?;
+        if !avoid.contains(&port) {
+            return Some(port);
+        }
+        rejected.push(listener);
+    }
+    None
 }
 
 #[cfg(test)]
@@ -258,37 +578,196 @@ mod tests {
         assert!(!is_local_port_free(Port::ipv6(port)));
     }
 
+    #[test]
+    #[serial]
+    fn a_dual_stack_port_should_be_free_only_for_both_families() {
+        let port = find_free_ipv4_and_ipv6_port();
+        assert!(is_local_port_free(Port::both(port)));
+
+        let ipv6 = TcpListener::bind((Ipv6Addr::LOCALHOST, port)).unwrap();
+        assert!(is_local_port_free(Port::ipv4(port)));
+        assert!(!is_local_port_free(Port::both(port)));
+        drop(ipv6);
+
+        let _ipv4 = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).unwrap();
+        assert!(is_local_port_free(Port::ipv6(port)));
+        assert!(!is_local_port_free(Port::both(port)));
+        assert_eq!(port, Port::both(port).number());
+    }
+
+    #[test]
+    #[serial]
+    fn an_address_with_a_listener_should_not_be_bindable() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        assert!(!is_addr_bindable(Ipv4Addr::LOCALHOST.into(), port));
+        assert!(!is_addr_bindable(Ipv4Addr::UNSPECIFIED.into(), port));
+        // The whole 127.0.0.0/8 block is assigned to the loopback interface on Linux
+        #[cfg(target_os = "linux")]
+        assert!(is_addr_bindable(Ipv4Addr::new(127, 0, 0, 2).into(), port));
+
+        drop(listener);
+        assert!(is_addr_bindable(Ipv4Addr::LOCALHOST.into(), port));
+    }
+
+    #[test]
+    #[serial]
+    fn should_report_the_bindability_of_each_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let ips = [Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()];
+
+        assert_eq!(
+            HashMap::from([(ips[0], false), (ips[1], true)]),
+            bindability_by_ip(&ips, port)
+        );
+        assert!(bindability_by_ip(&[], port).is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn a_free_port_should_pass_the_bind_and_connect_self_test() {
+        let port = free_local_ipv4_port().unwrap();
+        assert!(verify_bind_and_connect(port));
+        assert!(is_local_ipv4_port_free(port));
+
+        let _listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).unwrap();
+        assert!(!verify_bind_and_connect(port));
+    }
+
     #[test]
     #[serial]
     fn should_return_an_unused_port_in_range() {
         let free_port = free_local_port().unwrap();
-        let min = free_port - 100;
-        let max = free_port;
-        let port_found = free_local_port_in_range(min..max).unwrap();
-        assert!(port_found >= min);
-        assert!(port_found <= max);
+        let range = range_around(free_port, 100);
+        let port_found = free_local_port_in_range(range.clone()).unwrap();
+        assert!(range.contains(&port_found));
     }
 
     #[test]
     #[serial]
     fn should_return_an_unused_ipv4_port_in_range() {
         let free_port = free_local_ipv4_port().unwrap();
-        let min = free_port - 100;
-        let max = free_port;
-        let port_found = free_local_ipv4_port_in_range(min..max).unwrap();
-        assert!(port_found >= min);
-        assert!(port_found <= max);
+        let range = range_around(free_port, 100);
+        let port_found = free_local_ipv4_port_in_range(range.clone()).unwrap();
+        assert!(range.contains(&port_found));
+    }
+
+    #[test]
+    #[serial]
+    fn should_search_a_range_up_to_the_last_port() {
+        let port_found = free_local_ipv4_port_in_range(65530..=65535);
+        assert!(port_found.is_none_or(|port| port >= 65530));
+        assert_eq!(
+            is_local_ipv4_port_free(65535),
+            free_local_ipv4_port_in_range(65535..=65535).is_some()
+        );
     }
 
     #[test]
     #[serial]
     fn should_return_an_unused_ipv6_port_in_range() {
         let free_port = free_local_ipv6_port().unwrap();
-        let min = free_port - 100;
-        let max = free_port;
-        let port_found = free_local_ipv6_port_in_range(min..max).unwrap();
-        assert!(port_found >= min);
-        assert!(port_found <= max);
+        let range = range_around(free_port, 100);
+        let port_found = free_local_ipv6_port_in_range(range.clone()).unwrap();
+        assert!(range.contains(&port_found));
+    }
+
+    #[test]
+    #[serial]
+    fn should_return_an_unused_port_with_the_time_taken() {
+        let start = Instant::now();
+        let (port, elapsed) = free_local_port_timed();
+        assert!(is_local_ipv4_port_free(port.unwrap()));
+        assert!(elapsed <= start.elapsed());
+    }
+
+    #[test]
+    #[serial]
+    fn should_return_an_immediately_reusable_port() {
+        let port = free_local_port_immediately_reusable().unwrap();
+        assert!(TcpListener::bind((Ipv4Addr::LOCALHOST, port)).is_ok());
+    }
+
+    #[test]
+    #[serial]
+    fn should_return_an_unused_port_not_in_the_avoid_list() {
+        let avoid = [free_local_port().unwrap(), free_local_port().unwrap()];
+        let result = free_local_port_avoiding(&avoid).unwrap();
+        assert!(!avoid.contains(&result));
+        assert!(is_local_port_free(result));
+    }
+
+    #[test]
+    #[serial]
+    fn should_return_the_hint_or_the_next_free_port() {
+        let free_port = free_local_port().unwrap();
+        assert_eq!(Some(free_port), free_local_port_from_hint(free_port));
+
+        let (port, _listener) = reserve_local_port_from_hint(free_port).unwrap();
+        assert_eq!(free_port, port);
+        let next = free_local_port_from_hint(free_port).unwrap();
+        assert!(next > free_port);
+        assert!(is_local_port_free(next));
+    }
+
+    #[test]
+    #[serial]
+    fn should_return_the_lowest_unprivileged_free_port() {
+        let (port, listener) = free_local_port_unprivileged().unwrap();
+        assert!(port >= 1024);
+        assert_eq!(port, listener.local_addr().unwrap().port());
+        assert!(!is_local_ipv4_port_free(port));
+    }
+
+    #[test]
+    fn ports_should_be_ordered_by_number_then_family() {
+        let ports: std::collections::BTreeSet<Port> = [
+            Port::ipv6(80),
+            Port::ipv4(8080),
+            Port::ipv4(80),
+            Port::ipv6(22),
+            Port::both(80),
+            Port::ipv4(80),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            vec![
+                Port::ipv6(22),
+                Port::ipv4(80),
+                Port::ipv6(80),
+                Port::both(80),
+                Port::ipv4(8080)
+            ],
+            ports.into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn a_port_from_a_socket_address_should_keep_its_family() {
+        let ipv4: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let ipv6: SocketAddr = "[::1]:8080".parse().unwrap();
+        assert_eq!(Port::ipv4(8080), Port::from(ipv4));
+        assert_eq!(Port::ipv6(8080), Port::from(ipv6));
+        assert_eq!(
+            Port::ipv4(22),
+            Port::from(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 22))
+        );
+        assert_eq!(
+            Port::ipv6(22),
+            Port::from(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 22, 0, 0))
+        );
+    }
+
+    #[test]
+    fn range_around_should_saturate_at_the_port_boundaries() {
+        assert_eq!(900..=1100, range_around(1000, 100));
+        assert_eq!(0..=105, range_around(5, 100));
+        assert_eq!(65430..=65535, range_around(65530, 100));
+        assert_eq!(0..=65535, range_around(0, u16::MAX));
+        assert_eq!(65535..=65535, range_around(65535, 0));
     }
 
     #[test]
@@ -427,6 +906,61 @@ mod tests {
         assert!(elapsed < 2 * timeout);
     }
 
+    #[test]
+    #[serial]
+    fn is_port_reachable_default_should_use_the_default_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        assert!(is_port_reachable_default(address));
+
+        drop(listener);
+        assert!(!is_port_reachable_default(address));
+    }
+
+    #[test]
+    #[serial]
+    fn loopback_reachability_should_depend_on_the_ip_version() {
+        let listener = TcpListener::bind("[::1]:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let timeout = Duration::from_millis(100);
+        assert!(is_ipv6_loopback_reachable(port, timeout));
+        assert!(!is_ipv4_loopback_reachable(port, timeout));
+
+        drop(listener);
+        let _listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).unwrap();
+        assert!(is_ipv4_loopback_reachable(port, timeout));
+        assert!(!is_ipv6_loopback_reachable(port, timeout));
+    }
+
+    #[test]
+    #[serial]
+    fn is_port_reachable_secs_should_use_the_timeout_in_seconds() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        assert!(is_port_reachable_secs(address, 1));
+
+        drop(listener);
+        assert!(!is_port_reachable_secs(address, 1));
+    }
+
+    #[test]
+    #[serial]
+    fn should_try_each_address_with_its_own_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap();
+        let closed = SocketAddr::from((Ipv6Addr::LOCALHOST, free_local_ipv6_port().unwrap()));
+
+        assert!(is_reachable_with_addr_timeouts(&[
+            (closed, Duration::from_millis(10)),
+            (open, Duration::from_millis(100)),
+        ]));
+        assert!(!is_reachable_with_addr_timeouts(&[(
+            closed,
+            Duration::from_millis(10)
+        )]));
+        assert!(!is_reachable_with_addr_timeouts(&[]));
+    }
+
     #[test]
     #[serial]
     fn free_port_with_timeout_should_resolve_domain_name() {
@@ -0,0 +1,374 @@
+//! Best-effort inspection of the operating system socket tables.
+
+use std::net::Ipv6Addr;
+#[cfg(not(target_os = "linux"))]
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
+use std::ops::RangeInclusive;
+#[cfg(not(target_os = "linux"))]
+use std::time::Duration;
+
+use crate::free_local_port_avoiding;
+
+/// Returns whether a socket is listening on the local port, as reported by the OS.
+///
+/// Unlike [`crate::is_local_port_free`], this does not attempt a bind, so it also detects
+/// listeners that would not prevent binding (e.g. a process owned by another user bound to a
+/// different local address).
+///
+/// On Linux this reads `/proc/net/tcp` and `/proc/net/tcp6`. On other platforms it falls back
+/// to a short connection attempt to the IPv4 and IPv6 loopback addresses.
+pub fn is_local_port_listening(port: u16) -> bool {
+    imp::is_local_port_listening(port)
+}
+
+/// Returns the local ports with a listening TCP socket, as reported by the OS, sorted and without duplicates.
+///
+/// On Linux this reads `/proc/net/tcp` and `/proc/net/tcp6`, so it includes the sockets
+/// bound to any local address. On other platforms the socket tables are not inspected
+/// and the list is always empty.
+pub fn local_listening_ports() -> Vec<u16> {
+    let mut ports = imp::listening_ports();
+    ports.sort_unstable();
+    ports.dedup();
+    ports
+}
+
+/// Returns an available localhost port for IPv4 that no connection is currently using in the TIME_WAIT state.
+///
+/// Ports still in TIME_WAIT can be handed out by the OS as ephemeral ports but may cause
+/// "address already in use" errors for servers binding them without `SO_REUSEADDR`.
+/// On Linux the TIME_WAIT ports are read from `/proc/net/tcp` and `/proc/net/tcp6`;
+/// on other platforms this is the same as [`crate::free_local_ipv4_port`].
+pub fn free_local_port_fresh() -> Option<u16> {
+    free_local_port_avoiding(&imp::time_wait_ports())
+}
+
+/// Returns whether the OS keeps a port out of the ephemeral port allocation.
+///
+/// Such a port looks free but is never handed out when binding port 0, and on Windows it
+/// cannot even be bound explicitly. On Linux this reads `/proc/sys/net/ipv4/ip_local_reserved_ports`;
+/// on Windows this runs `netsh int ipv4 show excludedportrange protocol=tcp`.
+/// On other platforms, or if the list cannot be read, this returns `false`.
+pub fn is_port_reserved_by_os(port: u16) -> bool {
+    reserved_port_ranges()
+        .iter()
+        .any(|range| range.contains(&port))
+}
+
+/// Returns whether the host has an IPv6 address usable to reach other hosts, i.e. a global or
+/// unique local address, not only the loopback and link-local ones.
+///
+/// On hosts where IPv6 is enabled but not configured, connections to IPv6 addresses only fail
+/// after the timeout: checking this first allows skipping them.
+/// On Linux this reads the addresses of the interfaces from `/proc/net/if_inet6`, ignoring those
+/// still being checked for duplicates. On other platforms this asks the OS which local address
+/// it would use to reach a global IPv6 address, without sending any packet.
+pub fn ipv6_usable() -> bool {
+    imp::local_ipv6_addrs().iter().any(is_usable_ipv6)
+}
+
+/// Returns whether an IPv6 address is a global unicast (`2000::/3`) or unique local (`fc00::/7`)
+/// address, leaving out the documentation prefixes `2001:db8::/32` and `3fff::/20`
+fn is_usable_ipv6(ip: &Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    let global = (segments[0] & 0xe000) == 0x2000;
+    let documentation = (segments[0] == 0x2001 && segments[1] == 0x0db8)
+        || (segments[0] == 0x3fff && (segments[1] & 0xf000) == 0);
+    let unique_local = (segments[0] & 0xfe00) == 0xfc00;
+    (global && !documentation) || unique_local
+}
+
+/// Returns the port ranges kept out of the ephemeral port allocation, see [`is_port_reserved_by_os`]
+pub(crate) fn reserved_port_ranges() -> Vec<RangeInclusive<u16>> {
+    imp::reserved_port_ranges()
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::fs;
+    use std::net::Ipv6Addr;
+    use std::ops::RangeInclusive;
+
+    /// The socket state of a listening socket in `/proc/net/tcp`
+    pub(crate) const TCP_LISTEN: u8 = 0x0A;
+    /// The socket state of a connection waiting for stray packets after being closed
+    pub(crate) const TCP_TIME_WAIT: u8 = 0x06;
+
+    const PROC_NET_TCP: [&str; 2] = ["/proc/net/tcp", "/proc/net/tcp6"];
+    const IP_LOCAL_RESERVED_PORTS: &str = "/proc/sys/net/ipv4/ip_local_reserved_ports";
+    const IF_INET6: &str = "/proc/net/if_inet6";
+    /// The flags of an address still being checked for duplicates (`IFA_F_TENTATIVE`)
+    /// or found to be a duplicate (`IFA_F_DADFAILED`)
+    const IFA_F_UNUSABLE: u8 = 0x40 | 0x08;
+
+    pub(crate) fn local_ipv6_addrs() -> Vec<Ipv6Addr> {
+        fs::read_to_string(IF_INET6)
+            .map(|content| {
+                content
+                    .lines()
+                    .filter_map(parse_if_inet6_line)
+                    .filter(|(_ip, flags)| flags & IFA_F_UNUSABLE == 0)
+                    .map(|(ip, _flags)| ip)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Parses a line of `/proc/net/if_inet6`, returning the address and its flags
+    pub(crate) fn parse_if_inet6_line(line: &str) -> Option<(Ipv6Addr, u8)> {
+        let mut fields = line.split_whitespace();
+        let ip = u128::from_str_radix(fields.next()?, 16).ok()?;
+        let flags = fields.nth(3)?;
+        Some((Ipv6Addr::from(ip), u8::from_str_radix(flags, 16).ok()?))
+    }
+
+    pub(crate) fn reserved_port_ranges() -> Vec<RangeInclusive<u16>> {
+        fs::read_to_string(IP_LOCAL_RESERVED_PORTS)
+            .map(|content| parse_reserved_ports(&content))
+            .unwrap_or_default()
+    }
+
+    /// Parses the content of `ip_local_reserved_ports`, a comma separated list of ports
+    /// and ranges such as `8080,9000-9100`. Invalid entries are skipped.
+    pub(crate) fn parse_reserved_ports(content: &str) -> Vec<RangeInclusive<u16>> {
+        content
+            .trim()
+            .split(',')
+            .filter_map(|entry| {
+                let (start, end) = entry.split_once('-').unwrap_or((entry, entry));
+                Some(start.trim().parse().ok()?..=end.trim().parse().ok()?)
+            })
+            .collect()
+    }
+
+    pub(crate) fn is_local_port_listening(port: u16) -> bool {
+        proc_net_tcp_entries().any(|(local_port, state)| local_port == port && state == TCP_LISTEN)
+    }
+
+    pub(crate) fn listening_ports() -> Vec<u16> {
+        proc_net_tcp_entries()
+            .filter(|(_port, state)| *state == TCP_LISTEN)
+            .map(|(port, _state)| port)
+            .collect()
+    }
+
+    pub(crate) fn time_wait_ports() -> Vec<u16> {
+        proc_net_tcp_entries()
+            .filter(|(_port, state)| *state == TCP_TIME_WAIT)
+            .map(|(port, _state)| port)
+            .collect()
+    }
+
+    /// Returns the `(local port, state)` pairs of all the entries of `/proc/net/tcp{,6}`.
+    /// Unreadable tables are skipped.
+    pub(crate) fn proc_net_tcp_entries() -> impl Iterator<Item = (u16, u8)> {
+        PROC_NET_TCP
+            .iter()
+            .filter_map(|path| fs::read_to_string(path).ok())
+            .flat_map(|content| {
+                content
+                    .lines()
+                    .skip(1)
+                    .filter_map(parse_proc_net_tcp_line)
+                    .collect::<Vec<_>>()
+            })
+    }
+
+    /// Parses a line of `/proc/net/tcp{,6}`, returning the local port and the socket state.
+    pub(crate) fn parse_proc_net_tcp_line(line: &str) -> Option<(u16, u8)> {
+        let mut fields = line.split_whitespace();
+        let local_address = fields.nth(1)?;
+        let state = fields.nth(1)?;
+        let (_ip, port) = local_address.rsplit_once(':')?;
+        Some((
+            u16::from_str_radix(port, 16).ok()?,
+            u8::from_str_radix(state, 16).ok()?,
+        ))
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use super::*;
+
+    const PROBE_TIMEOUT: Duration = Duration::from_millis(100);
+
+    pub(crate) fn is_local_port_listening(port: u16) -> bool {
+        [
+            SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+            SocketAddr::from((Ipv6Addr::LOCALHOST, port)),
+        ]
+        .iter()
+        .any(|address| TcpStream::connect_timeout(address, PROBE_TIMEOUT).is_ok())
+    }
+
+    pub(crate) fn listening_ports() -> Vec<u16> {
+        Vec::new()
+    }
+
+    /// Returns the local address the OS would use to reach a global IPv6 address.
+    /// Connecting a UDP socket only selects the route, no packet is sent.
+    pub(crate) fn local_ipv6_addrs() -> Vec<Ipv6Addr> {
+        let route = || {
+            let socket = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).ok()?;
+            // An address of the documentation prefix, routed through the default route like any global address
+            socket
+                .connect((Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1), 9))
+                .ok()?;
+            match socket.local_addr().ok()?.ip() {
+                IpAddr::V6(ip) => Some(ip),
+                IpAddr::V4(_) => None,
+            }
+        };
+        route().into_iter().collect()
+    }
+
+    #[cfg(windows)]
+    pub(crate) fn reserved_port_ranges() -> Vec<RangeInclusive<u16>> {
+        std::process::Command::new("netsh")
+            .args(["int", "ipv4", "show", "excludedportrange", "protocol=tcp"])
+            .output()
+            .map(|output| parse_excluded_port_ranges(&String::from_utf8_lossy(&output.stdout)))
+            .unwrap_or_default()
+    }
+
+    /// Parses the output of `netsh int ipv4 show excludedportrange`, made of a header followed
+    /// by a line with the start and end port of each range
+    #[cfg(windows)]
+    pub(crate) fn parse_excluded_port_ranges(output: &str) -> Vec<RangeInclusive<u16>> {
+        output
+            .lines()
+            .filter_map(|line| {
+                let mut ports = line.split_whitespace();
+                let start = ports.next()?.parse().ok()?;
+                let end = ports.next()?.parse().ok()?;
+                Some(start..=end)
+            })
+            .collect()
+    }
+
+    #[cfg(not(windows))]
+    pub(crate) fn reserved_port_ranges() -> Vec<RangeInclusive<u16>> {
+        Vec::new()
+    }
+
+    pub(crate) fn time_wait_ports() -> Vec<u16> {
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serial_test::serial;
+    use std::net::TcpListener;
+
+    #[test]
+    #[serial]
+    fn a_bound_listener_should_be_listening() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(is_local_port_listening(port));
+
+        drop(listener);
+        assert!(!is_local_port_listening(port));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[serial]
+    fn should_list_the_listening_ports() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let ports = local_listening_ports();
+        assert!(ports.contains(&port));
+        assert!(ports.windows(2).all(|pair| pair[0] < pair[1]));
+
+        drop(listener);
+        assert!(!local_listening_ports().contains(&port));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn should_parse_proc_net_tcp_lines() {
+        let line = "   1: 0100007F:BC8F 00000000:0000 0A 00000000:00000000 00:00000000 00000000 65534        0 926 1 0000000069538217 100 0 0 10 0";
+        assert_eq!(
+            Some((0xBC8F, imp::TCP_LISTEN)),
+            imp::parse_proc_net_tcp_line(line)
+        );
+        assert_eq!(None, imp::parse_proc_net_tcp_line("  sl  local_address"));
+
+        let line = "   2: 0100007F:1F90 0100007F:C350 06 00000000:00000000 03:00000F3A 00000000     0        0 0 3 0000000000000000";
+        assert_eq!(
+            Some((0x1F90, imp::TCP_TIME_WAIT)),
+            imp::parse_proc_net_tcp_line(line)
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn should_parse_the_reserved_ports() {
+        assert_eq!(
+            vec![8080..=8080, 9000..=9100],
+            imp::parse_reserved_ports("8080,9000-9100\n")
+        );
+        assert!(imp::parse_reserved_ports("\n").is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn should_parse_if_inet6_lines() {
+        assert_eq!(
+            Some(("fd00::2".parse().unwrap(), 0x80)),
+            imp::parse_if_inet6_line("fd000000000000000000000000000002 04 40 00 80     eth0")
+        );
+        assert_eq!(
+            Some((Ipv6Addr::LOCALHOST, 0x80)),
+            imp::parse_if_inet6_line("00000000000000000000000000000001 01 80 10 80       lo")
+        );
+        assert_eq!(None, imp::parse_if_inet6_line(""));
+    }
+
+    #[test]
+    fn only_global_and_unique_local_addresses_should_be_usable() {
+        assert!(is_usable_ipv6(&"2606:4700::1111".parse().unwrap()));
+        assert!(is_usable_ipv6(&"3fff:1000::1".parse().unwrap()));
+        assert!(is_usable_ipv6(&"fd00::2".parse().unwrap()));
+        assert!(!is_usable_ipv6(&Ipv6Addr::LOCALHOST));
+        assert!(!is_usable_ipv6(&Ipv6Addr::UNSPECIFIED));
+        assert!(!is_usable_ipv6(&"fe80::1".parse().unwrap()));
+        assert!(!is_usable_ipv6(&"ff02::1".parse().unwrap()));
+        assert!(!is_usable_ipv6(&"::ffff:192.0.2.1".parse().unwrap()));
+        assert!(!is_usable_ipv6(&"2001:db8::1".parse().unwrap()));
+        assert!(!is_usable_ipv6(&"3fff::1".parse().unwrap()));
+        assert!(!is_usable_ipv6(&"fec0::1".parse().unwrap()));
+        assert!(!is_usable_ipv6(&"100::1".parse().unwrap()));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn should_parse_the_excluded_port_ranges() {
+        let output = "\r\nProtocol tcp Port Exclusion Ranges\r\n\r\nStart Port    End Port\r\n----------    --------\r\n      5357        5357\r\n     50000       50059     *\r\n\r\n* - Administered port exclusions.\r\n";
+        assert_eq!(
+            vec![5357..=5357, 50000..=50059],
+            imp::parse_excluded_port_ranges(output)
+        );
+    }
+
+    #[test]
+    fn unreserved_ports_should_not_be_reported() {
+        let reserved = imp::reserved_port_ranges();
+        let port = (1..=u16::MAX)
+            .find(|port| !reserved.iter().any(|range| range.contains(port)))
+            .unwrap();
+        assert!(!is_port_reserved_by_os(port));
+    }
+
+    #[test]
+    #[serial]
+    fn a_fresh_port_should_be_free() {
+        let port = free_local_port_fresh().unwrap();
+        assert!(crate::is_local_ipv4_port_free(port));
+    }
+}
@@ -0,0 +1,499 @@
+//! Reachability checks through proxies.
+
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::reachability::{connect_first, set_timeouts, Deadline};
+
+/// The maximum size of the response headers accepted from an HTTP proxy
+const MAX_HTTP_RESPONSE_HEAD: usize = 8 * 1024;
+
+/// Returns whether a target is reachable through an HTTP proxy, i.e. whether the proxy
+/// establishes a tunnel in answer to a `CONNECT target_host:target_port HTTP/1.1` request.
+/// The timeout bounds the whole exchange with the proxy.
+pub fn is_port_reachable_via_http_proxy(
+    proxy: SocketAddr,
+    target_host: &str,
+    target_port: u16,
+    timeout: Duration,
+) -> bool {
+    http_connect(proxy, target_host, target_port, timeout).unwrap_or(false)
+}
+
+fn http_connect(
+    proxy: SocketAddr,
+    target_host: &str,
+    target_port: u16,
+    timeout: Duration,
+) -> io::Result<bool> {
+    let deadline = Deadline::after(timeout);
+    let mut stream = TcpStream::connect_timeout(&proxy, timeout)?;
+
+    let authority = if target_host.contains(':') {
+        format!("[{target_host}]:{target_port}")
+    } else {
+        format!("{target_host}:{target_port}")
+    };
+    set_timeouts(&stream, deadline)?;
+    stream.write_all(
+        format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n\r\n").as_bytes(),
+    )?;
+
+    // Only the head of the response matters, the tunnel is dropped right after
+    let mut head = Vec::new();
+    let mut buffer = [0u8; 512];
+    while !contains_end_of_head(&head) && head.len() < MAX_HTTP_RESPONSE_HEAD {
+        set_timeouts(&stream, deadline)?;
+        let read = stream.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&buffer[..read]);
+    }
+    Ok(is_success_status_line(&head))
+}
+
+fn contains_end_of_head(response: &[u8]) -> bool {
+    response.windows(4).any(|window| window == b"\r\n\r\n")
+}
+
+/// Returns whether the response starts with an HTTP status line with a 2xx status code
+fn is_success_status_line(response: &[u8]) -> bool {
+    let status_line = response
+        .split(|byte| *byte == b'\n')
+        .next()
+        .and_then(|line| std::str::from_utf8(line).ok())
+        .unwrap_or_default();
+    let mut parts = status_line.split_whitespace();
+    matches!(
+        (parts.next(), parts.next()),
+        (Some(version), Some(status)) if version.starts_with("HTTP/1.") && status.len() == 3 && status.starts_with('2')
+    )
+}
+
+/// The version of the SOCKS protocol
+const SOCKS5_VERSION: u8 = 5;
+/// The SOCKS5 authentication method that requires no authentication
+const SOCKS5_NO_AUTHENTICATION: u8 = 0;
+/// The SOCKS5 command establishing a TCP connection
+const SOCKS5_CONNECT: u8 = 1;
+
+/// Returns whether a target is reachable through a chain of SOCKS5 proxies, i.e. whether each proxy
+/// establishes a connection to the next one and the last proxy establishes one to the target.
+///
+/// A connection is made to the first proxy, then a SOCKS5 `CONNECT` request is sent through the
+/// tunnel built so far for each following hop. Only proxies requiring no authentication are supported.
+/// Host names are resolved by the last proxy. Returns `false` if the chain is empty.
+/// The timeout bounds the whole chain.
+pub fn is_port_reachable_via_proxy_chain(
+    proxies: &[SocketAddr],
+    target: &str,
+    port: u16,
+    timeout: Duration,
+) -> bool {
+    socks5_chain(proxies, target, port, timeout).unwrap_or(false)
+}
+
+fn socks5_chain(
+    proxies: &[SocketAddr],
+    target: &str,
+    port: u16,
+    timeout: Duration,
+) -> io::Result<bool> {
+    let deadline = Deadline::after(timeout);
+    let Some((first, others)) = proxies.split_first() else {
+        return Ok(false);
+    };
+    let mut stream = TcpStream::connect_timeout(first, timeout)?;
+    for proxy in others {
+        if !socks5_connect(&mut stream, &proxy.ip().to_string(), proxy.port(), deadline)? {
+            return Ok(false);
+        }
+    }
+    socks5_connect(&mut stream, target, port, deadline)
+}
+
+/// Asks the SOCKS5 proxy at the end of the stream to connect to a host,
+/// returning whether the proxy reported success
+fn socks5_connect(
+    stream: &mut TcpStream,
+    host: &str,
+    port: u16,
+    deadline: Deadline,
+) -> io::Result<bool> {
+    set_timeouts(stream, deadline)?;
+    stream.write_all(&[SOCKS5_VERSION, 1, SOCKS5_NO_AUTHENTICATION])?;
+    let mut choice = [0u8; 2];
+    set_timeouts(stream, deadline)?;
+    stream.read_exact(&mut choice)?;
+    if choice != [SOCKS5_VERSION, SOCKS5_NO_AUTHENTICATION] {
+        return Ok(false);
+    }
+
+    set_timeouts(stream, deadline)?;
+    stream.write_all(&socks5_connect_request(host, port)?)?;
+    let mut reply = [0u8; 4];
+    set_timeouts(stream, deadline)?;
+    stream.read_exact(&mut reply)?;
+    // The bound address is consumed, so the stream is positioned at the start of the tunnel
+    let address_len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            usize::from(len[0])
+        }
+        _ => return Err(io::ErrorKind::InvalidData.into()),
+    };
+    let mut bound = vec![0u8; address_len + 2];
+    set_timeouts(stream, deadline)?;
+    stream.read_exact(&mut bound)?;
+    Ok(reply[0] == SOCKS5_VERSION && reply[1] == 0)
+}
+
+/// Encodes a SOCKS5 `CONNECT` request for an IP address or a host name
+fn socks5_connect_request(host: &str, port: u16) -> io::Result<Vec<u8>> {
+    let mut request = vec![SOCKS5_VERSION, SOCKS5_CONNECT, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(1);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            let len = u8::try_from(host.len()).map_err(|_| io::ErrorKind::InvalidInput)?;
+            request.push(3);
+            request.push(len);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    Ok(request)
+}
+
+/// The version of the PROXY protocol header sent by [`is_port_reachable_with_proxy_protocol`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProxyProtocol {
+    /// The human readable header, e.g. `PROXY TCP4 192.0.2.1 192.0.2.2 50000 80`
+    V1,
+    /// The binary header
+    V2,
+}
+
+/// How long the connection is observed after sending the PROXY protocol header
+const PROXY_PROTOCOL_OBSERVATION: Duration = Duration::from_millis(200);
+
+/// The signature starting every PROXY protocol v2 header
+const PROXY_PROTOCOL_V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Attempts a TCP connection to an address, sends a PROXY protocol header describing the
+/// connection itself, and returns whether the backend accepted it.
+///
+/// Backends requiring the PROXY protocol close the connection as soon as the header is missing
+/// or invalid, so the backend is considered to accept the connection if it does not close it
+/// within 200 milliseconds of receiving the header, or if it already answered with some data.
+/// The timeout bounds the connection attempt and the sending of the header.
+pub fn is_port_reachable_with_proxy_protocol<A: ToSocketAddrs>(
+    address: A,
+    version: ProxyProtocol,
+    timeout: Duration,
+) -> bool {
+    send_proxy_protocol_header(address, version, timeout).unwrap_or(false)
+}
+
+fn send_proxy_protocol_header<A: ToSocketAddrs>(
+    address: A,
+    version: ProxyProtocol,
+    timeout: Duration,
+) -> io::Result<bool> {
+    let deadline = Deadline::after(timeout);
+    let Ok(mut stream) = connect_first(address, timeout) else {
+        return Ok(false);
+    };
+    let header = proxy_protocol_header(version, stream.local_addr()?, stream.peer_addr()?);
+    set_timeouts(&stream, deadline)?;
+    stream.write_all(&header)?;
+
+    stream.set_read_timeout(Some(PROXY_PROTOCOL_OBSERVATION))?;
+    match stream.read(&mut [0u8; 1]) {
+        Ok(read) => Ok(read > 0),
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            Ok(true)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Encodes the PROXY protocol header of a TCP connection from `source` to `destination`
+fn proxy_protocol_header(
+    version: ProxyProtocol,
+    source: SocketAddr,
+    destination: SocketAddr,
+) -> Vec<u8> {
+    match version {
+        ProxyProtocol::V1 => {
+            let protocol = match source {
+                SocketAddr::V4(_) => "TCP4",
+                SocketAddr::V6(_) => "TCP6",
+            };
+            format!(
+                "PROXY {protocol} {} {} {} {}\r\n",
+                source.ip(),
+                destination.ip(),
+                source.port(),
+                destination.port()
+            )
+            .into_bytes()
+        }
+        ProxyProtocol::V2 => {
+            let mut header = PROXY_PROTOCOL_V2_SIGNATURE.to_vec();
+            // Version 2, PROXY command
+            header.push(0x21);
+            let mut addresses = Vec::new();
+            match (source, destination) {
+                (SocketAddr::V4(source), SocketAddr::V4(destination)) => {
+                    // TCP over IPv4
+                    header.push(0x11);
+                    addresses.extend_from_slice(&source.ip().octets());
+                    addresses.extend_from_slice(&destination.ip().octets());
+                }
+                _ => {
+                    // TCP over IPv6
+                    header.push(0x21);
+                    addresses.extend_from_slice(&to_ipv6(source).octets());
+                    addresses.extend_from_slice(&to_ipv6(destination).octets());
+                }
+            }
+            addresses.extend_from_slice(&source.port().to_be_bytes());
+            addresses.extend_from_slice(&destination.port().to_be_bytes());
+            header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+            header.extend_from_slice(&addresses);
+            header
+        }
+    }
+}
+
+fn to_ipv6(address: SocketAddr) -> std::net::Ipv6Addr {
+    match address {
+        SocketAddr::V4(address) => address.ip().to_ipv6_mapped(),
+        SocketAddr::V6(address) => *address.ip(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serial_test::serial;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Starts a fake proxy answering a single request with the specified response
+    fn start_http_proxy(response: &'static str) -> (SocketAddr, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 256];
+            while !request.ends_with(b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (address, handle)
+    }
+
+    #[test]
+    #[serial]
+    fn should_be_reachable_if_the_proxy_establishes_the_tunnel() {
+        let (proxy, handle) = start_http_proxy("HTTP/1.1 200 Connection established\r\n\r\n");
+        assert!(is_port_reachable_via_http_proxy(
+            proxy,
+            "::1",
+            8080,
+            Duration::from_secs(1)
+        ));
+        assert!(handle
+            .join()
+            .unwrap()
+            .starts_with("CONNECT [::1]:8080 HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    #[serial]
+    fn should_not_be_reachable_if_the_proxy_refuses_the_tunnel() {
+        let (proxy, handle) = start_http_proxy("HTTP/1.1 403 Forbidden\r\n\r\n");
+        assert!(!is_port_reachable_via_http_proxy(
+            proxy,
+            "example.com",
+            443,
+            Duration::from_secs(1)
+        ));
+        assert!(handle
+            .join()
+            .unwrap()
+            .starts_with("CONNECT example.com:443 HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn should_parse_the_status_line() {
+        assert!(is_success_status_line(b"HTTP/1.0 200 OK\r\n\r\n"));
+        assert!(is_success_status_line(b"HTTP/1.1 204 No Content\r\n"));
+        assert!(!is_success_status_line(
+            b"HTTP/1.1 407 Proxy Authentication Required\r\n"
+        ));
+        assert!(!is_success_status_line(b"SSH-2.0-OpenSSH\r\n"));
+        assert!(!is_success_status_line(b""));
+    }
+
+    #[test]
+    fn should_encode_proxy_protocol_headers() {
+        let source: SocketAddr = "192.0.2.1:50000".parse().unwrap();
+        let destination: SocketAddr = "192.0.2.2:80".parse().unwrap();
+        assert_eq!(
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 50000 80\r\n".to_vec(),
+            proxy_protocol_header(ProxyProtocol::V1, source, destination)
+        );
+
+        let header = proxy_protocol_header(ProxyProtocol::V2, source, destination);
+        assert_eq!(PROXY_PROTOCOL_V2_SIGNATURE, header[..12]);
+        assert_eq!(
+            [0x21, 0x11, 0, 12, 192, 0, 2, 1, 192, 0, 2, 2, 0xC3, 0x50, 0, 80],
+            header[12..]
+        );
+
+        let header = proxy_protocol_header(
+            ProxyProtocol::V2,
+            "[::1]:50000".parse().unwrap(),
+            "[::1]:80".parse().unwrap(),
+        );
+        assert_eq!([0x21, 0x21, 0, 36], header[12..16]);
+        assert_eq!(16 + 36, header.len());
+    }
+
+    #[test]
+    #[serial]
+    fn should_be_reachable_if_the_backend_accepts_the_proxy_protocol_header() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let backend = thread::spawn(move || {
+            // Accepts the first connection and rejects the second one
+            let (mut accepted, _) = listener.accept().unwrap();
+            let mut header = [0u8; 64];
+            let _read = accepted.read(&mut header).unwrap();
+            let (rejected, _) = listener.accept().unwrap();
+            drop(rejected);
+            accepted
+        });
+
+        assert!(is_port_reachable_with_proxy_protocol(
+            address,
+            ProxyProtocol::V1,
+            Duration::from_secs(1)
+        ));
+        assert!(!is_port_reachable_with_proxy_protocol(
+            address,
+            ProxyProtocol::V2,
+            Duration::from_secs(1)
+        ));
+        drop(backend.join().unwrap());
+    }
+
+    /// Starts a fake SOCKS5 proxy accepting a single connection, on which it answers `hops` requests
+    /// as if each one was sent to the next proxy of a chain, replying to the last one with `last_reply`.
+    /// Returns the requests received.
+    fn start_socks5_proxy(
+        hops: usize,
+        last_reply: u8,
+    ) -> (SocketAddr, thread::JoinHandle<Vec<Vec<u8>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut requests = Vec::new();
+            for hop in 1..=hops {
+                let mut greeting = [0u8; 3];
+                stream.read_exact(&mut greeting).unwrap();
+                assert_eq!([5, 1, 0], greeting);
+                stream.write_all(&[5, 0]).unwrap();
+
+                let mut request = vec![0u8; 4];
+                stream.read_exact(&mut request).unwrap();
+                let address_len = match request[3] {
+                    1 => 4,
+                    4 => 16,
+                    _ => {
+                        let mut len = [0u8; 1];
+                        stream.read_exact(&mut len).unwrap();
+                        request.push(len[0]);
+                        usize::from(len[0])
+                    }
+                };
+                let mut address = vec![0u8; address_len + 2];
+                stream.read_exact(&mut address).unwrap();
+                request.extend_from_slice(&address);
+                requests.push(request);
+
+                let reply = if hop == hops { last_reply } else { 0 };
+                stream
+                    .write_all(&[5, reply, 0, 1, 0, 0, 0, 0, 0, 0])
+                    .unwrap();
+            }
+            requests
+        });
+        (address, handle)
+    }
+
+    #[test]
+    #[serial]
+    fn should_be_reachable_through_each_proxy_of_the_chain() {
+        let (proxy, handle) = start_socks5_proxy(2, 0);
+        let next: SocketAddr = "192.0.2.1:1080".parse().unwrap();
+        assert!(is_port_reachable_via_proxy_chain(
+            &[proxy, next],
+            "example.com",
+            443,
+            Duration::from_secs(1)
+        ));
+
+        let requests = handle.join().unwrap();
+        assert_eq!(vec![5, 1, 0, 1, 192, 0, 2, 1, 0x04, 0x38], requests[0]);
+        let mut expected = vec![5, 1, 0, 3, 11];
+        expected.extend_from_slice(b"example.com");
+        expected.extend_from_slice(&[0x01, 0xBB]);
+        assert_eq!(expected, requests[1]);
+    }
+
+    #[test]
+    #[serial]
+    fn should_not_be_reachable_if_a_proxy_refuses_the_connection() {
+        // Connection refused by the destination host
+        let (proxy, handle) = start_socks5_proxy(1, 5);
+        assert!(!is_port_reachable_via_proxy_chain(
+            &[proxy],
+            "::1",
+            8080,
+            Duration::from_secs(1)
+        ));
+        assert_eq!(4, handle.join().unwrap()[0][3]);
+        assert!(!is_port_reachable_via_proxy_chain(
+            &[],
+            "::1",
+            8080,
+            Duration::from_secs(1)
+        ));
+    }
+}
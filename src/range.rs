@@ -0,0 +1,465 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{Ipv4Addr, TcpListener};
+use std::ops::{Bound, RangeBounds, RangeInclusive};
+
+use crate::os::reserved_port_ranges;
+use crate::{
+    free_local_port_in_range_family, is_local_ipv4_port_free, reserve_free_port_on, Family, Ports,
+};
+
+/// The reason why [`try_free_local_port_in_range`] did not return a port
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeError {
+    /// The start of the range is greater than its end, which is most likely a configuration mistake
+    Inverted {
+        /// The start of the range
+        start: u16,
+        /// The end of the range
+        end: u16,
+    },
+    /// No port of the range is available
+    NoFreePort,
+}
+
+impl fmt::Display for RangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RangeError::Inverted { start, end } => {
+                write!(
+                    f,
+                    "the port range is inverted: {start} is greater than {end}"
+                )
+            }
+            RangeError::NoFreePort => write!(f, "no port of the range is available"),
+        }
+    }
+}
+
+impl std::error::Error for RangeError {}
+
+/// Like [`free_local_port_in_range`](crate::free_local_port_in_range), but a range whose start
+/// is greater than its end, e.g. `5000..3000`, is reported as [`RangeError::Inverted`] instead of
+/// being searched as an empty range.
+pub fn try_free_local_port_in_range<
+    P: Into<Ports<R>>,
+    R: RangeBounds<u16> + Iterator<Item = u16>,
+>(
+    port_range: P,
+) -> Result<u16, RangeError> {
+    let (port_range, family) = match port_range.into() {
+        Ports::Ipv4(port_range) => (port_range, Family::V4),
+        Ports::Ipv6(port_range) => (port_range, Family::V6),
+    };
+    if let Some((start, end)) = inverted_bounds(&port_range) {
+        return Err(RangeError::Inverted { start, end });
+    }
+    free_local_port_in_range_family(port_range, family).ok_or(RangeError::NoFreePort)
+}
+
+/// Returns the bounds of a range whose start is greater than its end
+fn inverted_bounds<R: RangeBounds<u16>>(range: &R) -> Option<(u16, u16)> {
+    let start = match range.start_bound() {
+        Bound::Included(start) | Bound::Excluded(start) => *start,
+        Bound::Unbounded => return None,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(end) | Bound::Excluded(end) => *end,
+        Bound::Unbounded => return None,
+    };
+    (start > end).then_some((start, end))
+}
+
+/// Options controlling which ports of a range are tried, and in which order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RangeOptions {
+    exclude: Vec<u16>,
+    step: u16,
+    shuffle_seed: Option<u64>,
+    exclude_os_reserved: bool,
+}
+
+impl RangeOptions {
+    /// Creates options that try every port of the range in ascending order
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Never tries the specified ports
+    pub fn exclude<I: IntoIterator<Item = u16>>(mut self, ports: I) -> Self {
+        self.exclude.extend(ports);
+        self
+    }
+
+    /// Only tries every `step`-th port, starting from the beginning of the range
+    pub fn step(mut self, step: u16) -> Self {
+        self.step = step;
+        self
+    }
+
+    /// Tries the ports in a random order. The same seed always produces the same order.
+    pub fn shuffle(mut self, seed: u64) -> Self {
+        self.shuffle_seed = Some(seed);
+        self
+    }
+
+    /// Never tries the ports that the OS keeps out of the ephemeral port allocation,
+    /// see [`is_port_reserved_by_os`](crate::is_port_reserved_by_os). They are read once per search.
+    pub fn exclude_os_reserved(mut self, exclude: bool) -> Self {
+        self.exclude_os_reserved = exclude;
+        self
+    }
+}
+
+/// Returns the ports of a range that a search with the specified options would try, in order.
+/// Stepping is applied first, then exclusions, including the ports reserved by the OS if requested,
+/// then shuffling. No port is bound.
+pub fn plan_range<R: RangeBounds<u16>>(range: R, options: &RangeOptions) -> Vec<u16> {
+    let (start, end) = inclusive_bounds(&range);
+    let reserved = if options.exclude_os_reserved {
+        reserved_port_ranges()
+    } else {
+        Vec::new()
+    };
+    let mut ports: Vec<u16> = (start..=end)
+        .step_by(usize::from(options.step.max(1)))
+        .map(|port| port as u16)
+        .filter(|port| !options.exclude.contains(port))
+        .filter(|port| !reserved.iter().any(|range| range.contains(port)))
+        .collect();
+    if let Some(seed) = options.shuffle_seed {
+        shuffle(&mut ports, seed);
+    }
+    ports
+}
+
+/// Returns an available localhost port for IPv4 within the specified range,
+/// trying the ports in the order planned by [`plan_range`].
+pub fn free_local_port_in_range_with<R: RangeBounds<u16>>(
+    range: R,
+    options: &RangeOptions,
+) -> Option<u16> {
+    plan_range(range, options)
+        .into_iter()
+        .find(|port| is_local_ipv4_port_free(*port))
+}
+
+/// Returns an available localhost port for IPv4 within several ranges, e.g. to skip reserved
+/// sub-ranges. The ranges are searched in order, each one in ascending order.
+pub fn free_local_port_in_ranges(ranges: &[RangeInclusive<u16>]) -> Option<u16> {
+    ranges
+        .iter()
+        .find_map(|range| range.clone().find(|port| is_local_ipv4_port_free(*port)))
+}
+
+/// Returns an available localhost port for IPv4 within the `allowed` range, together with
+/// the listener keeping it reserved, for sandboxes that only permit a slice of the port range.
+///
+/// An ephemeral port is requested from the OS first; if it falls outside `allowed`, it is released
+/// and the ports of `allowed` are tried in ascending order instead.
+pub fn free_local_port_restricted(allowed: RangeInclusive<u16>) -> Option<(u16, TcpListener)> {
+    if let Ok((port, listener)) = reserve_free_port_on(Ipv4Addr::LOCALHOST.into()) {
+        if allowed.contains(&port) {
+            return Some((port, listener));
+        }
+    }
+    allowed.into_iter().find_map(|port| {
+        TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .ok()
+            .map(|listener| (port, listener))
+    })
+}
+
+/// Returns up to `count` distinct available localhost ports for IPv4 within the specified range,
+/// in ascending order. Fewer ports are returned if the range does not contain enough free ports.
+///
+/// Every port found is kept bound until the search is over, so the result contains no duplicates
+/// even if another process releases and takes ports in the meantime.
+pub fn free_local_ports_in_range<R: RangeBounds<u16>>(range: R, count: usize) -> Vec<u16> {
+    let (start, end) = inclusive_bounds(&range);
+    let mut listeners = Vec::new();
+    let mut ports = Vec::new();
+    for port in (start..=end).map(|port| port as u16) {
+        if ports.len() == count {
+            break;
+        }
+        if let Ok(listener) = TcpListener::bind((Ipv4Addr::LOCALHOST, port)) {
+            listeners.push(listener);
+            ports.push(port);
+        }
+    }
+    ports
+}
+
+/// Attempts to bind every port of the range on the localhost for IPv4 and groups the ports that
+/// could not be bound by the kind of the error, each group in ascending order.
+///
+/// Bindable ports are not reported, so an empty map means that every port of the range is free.
+/// When no port is free, this tells why, e.g. only [`io::ErrorKind::PermissionDenied`] errors mean
+/// that the range needs privileges the process does not have. Each listener is released right away.
+pub fn range_bind_diagnostics<R: RangeBounds<u16>>(range: R) -> HashMap<io::ErrorKind, Vec<u16>> {
+    let (start, end) = inclusive_bounds(&range);
+    let mut failures: HashMap<io::ErrorKind, Vec<u16>> = HashMap::new();
+    for port in (start..=end).map(|port| port as u16) {
+        if let Err(err) = TcpListener::bind((Ipv4Addr::LOCALHOST, port)) {
+            failures.entry(err.kind()).or_default().push(port);
+        }
+    }
+    failures
+}
+
+/// Returns a localhost port for IPv4 within the specified range derived from a key, e.g. a test name,
+/// so the same key gets the same port across runs as long as it is available.
+///
+/// The key is hashed with FNV-1a into the range. If the resulting port is busy, e.g. because
+/// another key collided with it, the following ports are tried in order, wrapping around to the
+/// start of the range; in that case the result is no longer guaranteed to be stable.
+/// Returns `None` if the range is empty or has no available port.
+pub fn port_for_key<R: RangeBounds<u16>>(key: &str, range: R) -> Option<u16> {
+    let (start, end) = inclusive_bounds(&range);
+    if start > end {
+        return None;
+    }
+    let len = end - start + 1;
+    let offset = (fnv1a(key.as_bytes()) % u64::from(len)) as u32;
+    (0..len)
+        .map(|i| (start + (offset + i) % len) as u16)
+        .find(|port| is_local_ipv4_port_free(*port))
+}
+
+/// The 64-bit FNV-1a hash, which is stable across platforms and versions unlike the std hashers
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Returns the inclusive bounds of a port range as `u32`, so the end of the range never overflows.
+/// The start is greater than the end if the range is empty.
+pub(crate) fn inclusive_bounds<R: RangeBounds<u16>>(range: &R) -> (u32, u32) {
+    let start = match range.start_bound() {
+        Bound::Included(start) => u32::from(*start),
+        Bound::Excluded(start) => u32::from(*start) + 1,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(end) => i64::from(*end),
+        Bound::Excluded(end) => i64::from(*end) - 1,
+        Bound::Unbounded => i64::from(u16::MAX),
+    };
+    match u32::try_from(end) {
+        Ok(end) => (start, end),
+        // The range ends before port 0, so it is empty
+        Err(_) => (1, 0),
+    }
+}
+
+/// Shuffles the ports with a Fisher-Yates shuffle driven by a xorshift generator
+fn shuffle(ports: &mut [u16], seed: u64) {
+    // xorshift never leaves the zero state, so a zero seed is replaced
+    let mut state = if seed == 0 {
+        0x9E37_79B9_7F4A_7C15
+    } else {
+        seed
+    };
+    for i in (1..ports.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let j = (state % (i as u64 + 1)) as usize;
+        ports.swap(i, j);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serial_test::serial;
+
+    #[test]
+    fn should_plan_the_whole_range_by_default() {
+        assert_eq!(vec![10, 11, 12], plan_range(10..=12, &RangeOptions::new()));
+        assert_eq!(vec![10, 11], plan_range(10..12, &RangeOptions::new()));
+        assert_eq!(
+            vec![65534, 65535],
+            plan_range(65534.., &RangeOptions::new())
+        );
+        assert!(plan_range(0..0, &RangeOptions::new()).is_empty());
+    }
+
+    #[test]
+    fn should_apply_step_and_exclusions() {
+        let options = RangeOptions::new().step(5).exclude([10]);
+        assert_eq!(vec![0, 5, 15, 20], plan_range(0..=20, &options));
+    }
+
+    #[test]
+    fn should_exclude_the_ports_reserved_by_the_os() {
+        let options = RangeOptions::new().exclude_os_reserved(true);
+        let plan = plan_range(1..=u16::MAX, &options);
+        let reserved = reserved_port_ranges();
+        assert!(plan
+            .iter()
+            .all(|port| !reserved.iter().any(|range| range.contains(port))));
+        let reserved_count: usize = reserved.iter().map(|range| range.len()).sum();
+        assert!(plan.len() >= usize::from(u16::MAX) - reserved_count);
+
+        let plan = plan_range(1..=u16::MAX, &RangeOptions::new());
+        assert_eq!(usize::from(u16::MAX), plan.len());
+    }
+
+    #[test]
+    fn shuffling_should_be_deterministic_for_a_seed() {
+        let options = RangeOptions::new().shuffle(42);
+        let plan = plan_range(1000..2000, &options);
+        assert_eq!(plan, plan_range(1000..2000, &options));
+        assert_ne!(plan, plan_range(1000..2000, &RangeOptions::new()));
+
+        let mut sorted = plan.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, plan_range(1000..2000, &RangeOptions::new()));
+    }
+
+    #[test]
+    #[serial]
+    fn should_return_a_free_port_from_the_plan() {
+        let free_port = crate::free_local_ipv4_port().unwrap();
+        let range = crate::range_around(free_port, 100);
+        let options = RangeOptions::new().exclude([free_port]).shuffle(7);
+
+        let port_found = free_local_port_in_range_with(range.clone(), &options).unwrap();
+        assert!(range.contains(&port_found));
+        assert_ne!(free_port, port_found);
+    }
+
+    #[test]
+    #[serial]
+    fn should_return_up_to_count_free_ports_from_the_range() {
+        let free_port = crate::free_local_ipv4_port().unwrap();
+        let range = crate::range_around(free_port, 100);
+        let _listener = TcpListener::bind((Ipv4Addr::LOCALHOST, free_port)).unwrap();
+
+        let ports = free_local_ports_in_range(range.clone(), 5);
+        assert_eq!(5, ports.len());
+        assert!(ports.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ports
+            .iter()
+            .all(|port| range.contains(port) && *port != free_port));
+        assert!(ports
+            .iter()
+            .all(|port| crate::is_local_ipv4_port_free(*port)));
+
+        assert!(free_local_ports_in_range(free_port..=free_port, 5).is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn an_inverted_range_should_be_an_error() {
+        // Built from variables, as the literal ranges would be rejected by clippy
+        let (start, end) = (5000, 3000);
+        assert_eq!(
+            Err(RangeError::Inverted {
+                start: 5000,
+                end: 3000
+            }),
+            try_free_local_port_in_range(start..end)
+        );
+        assert_eq!(
+            Err(RangeError::Inverted {
+                start: 5000,
+                end: 4999
+            }),
+            try_free_local_port_in_range(Ports::ipv6(start..=start - 1))
+        );
+        assert_eq!(None, crate::free_local_ipv4_port_in_range(start..end));
+        assert_eq!(
+            Err(RangeError::NoFreePort),
+            try_free_local_port_in_range(start..start)
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn should_search_a_single_port_range() {
+        let port = crate::free_local_ipv4_port().unwrap();
+        assert_eq!(Ok(port), try_free_local_port_in_range(port..=port));
+        assert_eq!(Ok(port), try_free_local_port_in_range(port..port + 1));
+
+        let _listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).unwrap();
+        assert_eq!(
+            Err(RangeError::NoFreePort),
+            try_free_local_port_in_range(port..=port)
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn should_group_the_unbindable_ports_by_error() {
+        let busy = TcpListener::bind("127.0.0.1:0").unwrap();
+        let busy_port = busy.local_addr().unwrap().port();
+
+        let failures = range_bind_diagnostics(busy_port..=busy_port);
+        assert_eq!(
+            HashMap::from([(io::ErrorKind::AddrInUse, vec![busy_port])]),
+            failures
+        );
+
+        drop(busy);
+        assert!(range_bind_diagnostics(busy_port..=busy_port).is_empty());
+    }
+
+    #[test]
+    fn fnv1a_should_match_the_reference_values() {
+        assert_eq!(0xcbf2_9ce4_8422_2325, fnv1a(b""));
+        assert_eq!(0xaf63_dc4c_8601_ec8c, fnv1a(b"a"));
+    }
+
+    #[test]
+    #[serial]
+    fn the_port_of_a_key_should_be_stable_unless_busy() {
+        let free_port = crate::free_local_ipv4_port().unwrap();
+        let range = crate::range_around(free_port, 100);
+
+        let port = port_for_key("my_test", range.clone()).unwrap();
+        assert!(range.contains(&port));
+        assert_eq!(Some(port), port_for_key("my_test", range.clone()));
+
+        let _listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).unwrap();
+        let fallback = port_for_key("my_test", range.clone()).unwrap();
+        assert_ne!(port, fallback);
+        assert!(range.contains(&fallback));
+
+        assert_eq!(None, port_for_key("my_test", port..=port));
+        assert_eq!(None, port_for_key("my_test", port..port));
+    }
+
+    #[test]
+    #[serial]
+    fn should_search_the_ranges_in_order() {
+        let busy = TcpListener::bind("127.0.0.1:0").unwrap();
+        let busy_port = busy.local_addr().unwrap().port();
+        let free_port = crate::free_local_ipv4_port().unwrap();
+
+        assert_eq!(
+            Some(free_port),
+            free_local_port_in_ranges(&[busy_port..=busy_port, free_port..=free_port])
+        );
+        assert_eq!(None, free_local_port_in_ranges(&[busy_port..=busy_port]));
+        assert_eq!(None, free_local_port_in_ranges(&[]));
+    }
+
+    #[test]
+    #[serial]
+    fn should_return_a_reserved_port_within_the_allowed_range() {
+        let (port, listener) = free_local_port_restricted(1..=u16::MAX).unwrap();
+        assert_eq!(port, listener.local_addr().unwrap().port());
+        assert!(!crate::is_local_ipv4_port_free(port));
+        drop(listener);
+
+        let (restricted, _listener) = free_local_port_restricted(port..=port).unwrap();
+        assert_eq!(port, restricted);
+        assert!(free_local_port_restricted(port..=port).is_none());
+    }
+}
@@ -0,0 +1,883 @@
+use std::io::{self, Read, Write};
+use std::net::{
+    IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, TcpStream, ToSocketAddrs, UdpSocket,
+};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{is_local_ipv4_port_free, is_port_reachable_with_timeout, Family};
+
+/// The outcome of a TCP connection attempt.
+///
+/// With the `serde` feature, it is serialized as a lowercase, snake case string (e.g. `"timed_out"`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Reachability {
+    /// The connection was established
+    Open,
+    /// The connection was actively refused, nothing is listening on the port
+    Refused,
+    /// The connection was reset or aborted by the peer during the handshake.
+    ///
+    /// Most stacks (Linux included) report a RST received in answer to the SYN as a refused connection,
+    /// so this is only reported when the OS distinguishes a reset from a refusal,
+    /// e.g. when a middlebox tears down a half-open connection.
+    Reset,
+    /// No answer was received within the timeout, the port may be filtered
+    TimedOut,
+    /// The address could not be resolved or the host could not be reached
+    Unreachable,
+}
+
+impl Reachability {
+    /// Returns whether the connection was established
+    pub fn is_open(&self) -> bool {
+        *self == Reachability::Open
+    }
+
+    pub(crate) fn from_error(err: &io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::ConnectionRefused => Reachability::Refused,
+            io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted => {
+                Reachability::Reset
+            }
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Reachability::TimedOut,
+            _ => Reachability::Unreachable,
+        }
+    }
+}
+
+/// Attempts a TCP connection to an address and returns the reason of the outcome.
+/// When the address resolves to several socket addresses, they are tried in order and the
+/// outcome of the last attempt is returned if none succeeded.
+pub fn port_reachability<A: ToSocketAddrs>(address: A, timeout: Duration) -> Reachability {
+    match connect_first(address, timeout) {
+        Ok(_stream) => Reachability::Open,
+        Err(reachability) => reachability,
+    }
+}
+
+/// Like [`port_reachability`], but also returns the IP version of the connection when it was
+/// established, e.g. to tell whether a host name answered over IPv4 or IPv6
+pub fn port_reachability_with_family<A: ToSocketAddrs>(
+    address: A,
+    timeout: Duration,
+) -> (Reachability, Option<Family>) {
+    match connect_first(address, timeout) {
+        Ok(stream) => (
+            Reachability::Open,
+            stream.peer_addr().ok().map(|peer| Family::of(&peer)),
+        ),
+        Err(reachability) => (reachability, None),
+    }
+}
+
+/// Attempts a TCP connection to a host and returns whether it succeeded, with separate bounds on
+/// the name resolution and on each connection attempt.
+///
+/// [`crate::is_port_reachable_with_timeout`] bounds the connection attempts only, while the
+/// resolution of a host name can take seconds on a slow resolver. Here the resolution runs in a
+/// separate thread and is given up after `resolve_timeout`. Each resolved address is then given up to
+/// `connect_timeout`, within a single deadline, so the whole call takes at most `resolve_timeout`
+/// plus `connect_timeout` however many addresses the host resolves to.
+/// IP addresses are used directly, without a resolution thread.
+pub fn is_port_reachable_with_timeouts(
+    host: &str,
+    port: u16,
+    resolve_timeout: Duration,
+    connect_timeout: Duration,
+) -> bool {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return connect_before(
+            vec![SocketAddr::new(ip, port)],
+            Deadline::after(connect_timeout),
+            connect_timeout,
+        );
+    }
+    let host = host.to_string();
+    reachable_with_timeouts(
+        move || Ok((host.as_str(), port).to_socket_addrs()?.collect()),
+        resolve_timeout,
+        connect_timeout,
+    )
+}
+
+/// Resolves with the provided closure within `resolve_timeout`, then connects to the resolved
+/// addresses within a deadline of `resolve_timeout` plus `connect_timeout` from now
+fn reachable_with_timeouts<F>(
+    resolve: F,
+    resolve_timeout: Duration,
+    connect_timeout: Duration,
+) -> bool
+where
+    F: FnOnce() -> io::Result<Vec<SocketAddr>> + Send + 'static,
+{
+    let deadline = Deadline::after(resolve_timeout.saturating_add(connect_timeout));
+    resolve_within(resolve, resolve_timeout)
+        .is_some_and(|addrs| connect_before(addrs, deadline, connect_timeout))
+}
+
+/// Connects to the first reachable address, giving each one up to `connect_timeout`
+/// but never going past the deadline
+fn connect_before(addrs: Vec<SocketAddr>, deadline: Deadline, connect_timeout: Duration) -> bool {
+    addrs.into_iter().any(|address| {
+        let remaining = deadline.remaining();
+        !remaining.is_zero()
+            && TcpStream::connect_timeout(&address, remaining.min(connect_timeout)).is_ok()
+    })
+}
+
+/// Runs a name resolution in a separate thread and returns its outcome,
+/// or `None` if it failed or did not complete within the timeout.
+/// A resolution that did not complete in time is left running in the background.
+pub(crate) fn resolve_within<F>(resolve: F, timeout: Duration) -> Option<Vec<SocketAddr>>
+where
+    F: FnOnce() -> io::Result<Vec<SocketAddr>> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        // The receiver is gone if the timeout already elapsed
+        let _ = sender.send(resolve());
+    });
+    receiver.recv_timeout(timeout).ok()?.ok()
+}
+
+/// Attempts a TCP connection to an address and returns a human readable explanation of the outcome,
+/// including the socket address of the last attempt, e.g. for the output of a command line tool:
+/// `connection refused — nothing is listening on 127.0.0.1:8080`.
+/// When the address resolves to several socket addresses they are tried in order, as with
+/// [`port_reachability`].
+pub fn explain_reachability<A: ToSocketAddrs>(address: A, timeout: Duration) -> String {
+    let addrs: Vec<SocketAddr> = match address.to_socket_addrs() {
+        Ok(addrs) => addrs.collect(),
+        Err(err) => return format!("unresolvable — the address could not be resolved: {err}"),
+    };
+    let mut last = None;
+    for address in addrs {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(_stream) => return format!("open — {address} accepted the connection"),
+            Err(err) => last = Some((address, Reachability::from_error(&err), err)),
+        }
+    }
+    match last {
+        None => "unresolvable — the address resolved to no socket address".to_string(),
+        Some((address, Reachability::Refused, _err)) => {
+            format!("connection refused — nothing is listening on {address}")
+        }
+        Some((address, Reachability::Reset, _err)) => format!(
+            "connection reset — {address} dropped the connection during the handshake, e.g. because of a middlebox"
+        ),
+        Some((address, Reachability::TimedOut, _err)) => format!(
+            "timed out after {timeout:?} — {address} may be filtered by a firewall or down"
+        ),
+        Some((address, _reachability, err)) => {
+            format!("unreachable — {address} could not be reached: {err}")
+        }
+    }
+}
+
+/// Returns whether a host name or IP address resolves to at least one address
+pub fn can_resolve(host: &str) -> bool {
+    (host, 0)
+        .to_socket_addrs()
+        .map(|mut addrs| addrs.next().is_some())
+        .unwrap_or(false)
+}
+
+/// Attempts a TCP connection to an address and returns the first socket address that accepted it.
+/// The socket address is returned as resolved rather than as reported by the connection,
+/// so IPv6 addresses keep their flow info and scope id.
+pub fn first_reachable_addr<A: ToSocketAddrs>(address: A, timeout: Duration) -> Option<SocketAddr> {
+    let addrs = address.to_socket_addrs().ok()?;
+    connect_any(addrs, timeout)
+        .ok()
+        .map(|(_stream, address)| address)
+}
+
+/// Like [`first_reachable_addr`], but also returns the IP version of the socket address
+/// that accepted the connection, see [`Family::of`]
+pub fn first_reachable_addr_with_family<A: ToSocketAddrs>(
+    address: A,
+    timeout: Duration,
+) -> Option<(SocketAddr, Family)> {
+    first_reachable_addr(address, timeout).map(|address| (address, Family::of(&address)))
+}
+
+/// Attempts a TCP connection to each IPv6 candidate in order and returns the first one that accepted it.
+/// This is useful to find the interface through which a link-local address is reachable,
+/// by trying the same address with the scope id of each candidate interface.
+pub fn first_reachable_ipv6_addr(
+    candidates: &[SocketAddrV6],
+    timeout: Duration,
+) -> Option<SocketAddrV6> {
+    candidates
+        .iter()
+        .find(|candidate| TcpStream::connect_timeout(&SocketAddr::V6(**candidate), timeout).is_ok())
+        .copied()
+}
+
+/// Whether a local service is reachable locally and through its public address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ForwardStatus {
+    /// The port is reachable both locally and through the public address
+    Both,
+    /// The port is reachable locally only, the port forward is missing or blocked
+    LocalOnly,
+    /// The port is reachable through the public address only, e.g. it is served by another host
+    ExternalOnly,
+    /// The port is not reachable at all
+    Neither,
+}
+
+/// Returns whether a port is reachable through a public IP address or host name.
+///
+/// This is a plain reachability check against the external address.
+/// For the result to be meaningful, it should be run from outside the local network:
+/// many routers do not support connecting to their own public address from inside (NAT hairpinning),
+/// so a forwarded port can look closed when checked from the LAN.
+pub fn is_port_open_externally(public_host: &str, port: u16, timeout: Duration) -> bool {
+    is_port_reachable_with_timeout((public_host, port), timeout)
+}
+
+/// Compares the reachability of a port on the loopback addresses with its reachability
+/// through a public IP address or host name, to diagnose port forwards.
+/// The same caveats of [`is_port_open_externally`] apply.
+pub fn compare_local_vs_external(port: u16, public_host: &str, timeout: Duration) -> ForwardStatus {
+    let local = is_port_reachable_with_timeout(
+        &[
+            SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+            SocketAddr::from((Ipv6Addr::LOCALHOST, port)),
+        ][..],
+        timeout,
+    );
+    let external = is_port_open_externally(public_host, port, timeout);
+    match (local, external) {
+        (true, true) => ForwardStatus::Both,
+        (true, false) => ForwardStatus::LocalOnly,
+        (false, true) => ForwardStatus::ExternalOnly,
+        (false, false) => ForwardStatus::Neither,
+    }
+}
+
+/// Attempts a TCP connection to a port of the IPv4 (`127.0.0.1`) and of the IPv6 (`::1`) loopback
+/// addresses and returns both outcomes, in this order.
+///
+/// A service listening on one IP version only is reachable through one loopback address but not
+/// through the other, e.g. a client resolving `localhost` to `::1` cannot reach a server bound to
+/// `127.0.0.1`: comparing the two outcomes shows this asymmetry in a single call.
+pub fn loopback_reachability(port: u16, timeout: Duration) -> (Reachability, Reachability) {
+    (
+        port_reachability((Ipv4Addr::LOCALHOST, port), timeout),
+        port_reachability((Ipv6Addr::LOCALHOST, port), timeout),
+    )
+}
+
+/// The state of a localhost port, to decide whether a service is already running
+/// or should be started
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PortState {
+    /// Something accepts connections on the port
+    ReachableLocally,
+    /// Nothing is bound to the port, so a service can be started on it
+    FreeLocally,
+    /// The port is bound but does not accept connections, e.g. a service still starting up
+    /// or a socket bound without listening
+    BusyButUnreachable,
+}
+
+/// Returns the state of a localhost port for IPv4, combining a reachability check with a bind test
+pub fn port_state(port: u16, timeout: Duration) -> PortState {
+    if is_port_reachable_with_timeout((Ipv4Addr::LOCALHOST, port), timeout) {
+        PortState::ReachableLocally
+    } else if is_local_ipv4_port_free(port) {
+        PortState::FreeLocally
+    } else {
+        PortState::BusyButUnreachable
+    }
+}
+
+/// Returns whether a local service is reachable through the IPv4 loopback address or, failing that,
+/// through the IP address of the interface of the default route.
+///
+/// This helps verifying that a server bound to the unspecified address (`0.0.0.0`) is reachable
+/// not only through the loopback interface. If the host has no default route, only the loopback
+/// address is tried.
+pub fn is_service_reachable_locally(port: u16, timeout: Duration) -> bool {
+    is_port_reachable_with_timeout((Ipv4Addr::LOCALHOST, port), timeout)
+        || default_route_ip().is_some_and(|ip| is_port_reachable_with_timeout((ip, port), timeout))
+}
+
+/// Returns the local IPv4 address the OS would use to reach a public address.
+/// Connecting a UDP socket only selects the route, no packet is sent.
+pub(crate) fn default_route_ip() -> Option<IpAddr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    // An address of the TEST-NET-2 block, routed through the default route like any public address
+    socket.connect((Ipv4Addr::new(198, 51, 100, 1), 9)).ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    (!ip.is_unspecified()).then_some(ip)
+}
+
+/// The maximum number of bytes of the response read by [`is_reachable_with_exchange`]
+const MAX_EXCHANGE_RESPONSE: usize = 64 * 1024;
+
+/// Attempts a TCP connection to an address, sends `send` and returns whether the response
+/// is accepted by `expect`, to check the liveness of a service at the application layer.
+///
+/// The response is read until `expect` accepts the bytes received so far, the server closes
+/// the connection, or 64 KiB have been received. The timeout bounds the connection attempt,
+/// then the whole exchange.
+pub fn is_reachable_with_exchange<A, F>(
+    address: A,
+    send: &[u8],
+    expect: F,
+    timeout: Duration,
+) -> bool
+where
+    A: ToSocketAddrs,
+    F: Fn(&[u8]) -> bool,
+{
+    exchange(address, send, expect, timeout).unwrap_or(false)
+}
+
+fn exchange<A, F>(address: A, send: &[u8], expect: F, timeout: Duration) -> io::Result<bool>
+where
+    A: ToSocketAddrs,
+    F: Fn(&[u8]) -> bool,
+{
+    let Ok(mut stream) = connect_first(address, timeout) else {
+        return Ok(false);
+    };
+    let deadline = Deadline::after(timeout);
+    set_timeouts(&stream, deadline)?;
+    stream.write_all(send)?;
+
+    let mut response = Vec::new();
+    let mut buffer = [0u8; 4096];
+    while response.len() < MAX_EXCHANGE_RESPONSE {
+        set_timeouts(&stream, deadline)?;
+        let read = stream.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        response.extend_from_slice(&buffer[..read]);
+        if expect(&response) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Attempts a TCP connection to an address, sends `request` and returns the time between the end
+/// of the request and the first byte of the response, to detect services that accept connections
+/// but hang instead of answering.
+///
+/// The timeout bounds the whole operation, from the first connection attempt to the first byte.
+/// Returns `None` if the connection fails, if the server closes it without answering, or if no byte
+/// arrives before the timeout.
+pub fn time_to_first_byte<A: ToSocketAddrs>(
+    address: A,
+    request: &[u8],
+    timeout: Duration,
+) -> Option<Duration> {
+    first_byte(address, request, timeout).ok().flatten()
+}
+
+fn first_byte<A: ToSocketAddrs>(
+    address: A,
+    request: &[u8],
+    timeout: Duration,
+) -> io::Result<Option<Duration>> {
+    let deadline = Deadline::after(timeout);
+    let mut stream = None;
+    for address in address.to_socket_addrs()? {
+        let remaining = deadline.remaining();
+        if remaining.is_zero() {
+            break;
+        }
+        if let Ok(connected) = TcpStream::connect_timeout(&address, remaining) {
+            stream = Some(connected);
+            break;
+        }
+    }
+    let Some(mut stream) = stream else {
+        return Ok(None);
+    };
+    set_timeouts(&stream, deadline)?;
+    stream.write_all(request)?;
+    let sent = Instant::now();
+    set_timeouts(&stream, deadline)?;
+    let read = stream.read(&mut [0u8; 1])?;
+    Ok((read > 0).then(|| sent.elapsed()))
+}
+
+/// The point in time after which an operation gives up, if any.
+///
+/// A timeout too large to be added to the current time, e.g. [`Duration::MAX`], gives no deadline
+/// rather than a panic.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Deadline(Option<Instant>);
+
+impl Deadline {
+    /// Returns the deadline `timeout` from now
+    pub(crate) fn after(timeout: Duration) -> Self {
+        Deadline(Instant::now().checked_add(timeout))
+    }
+
+    /// Returns the time left before the deadline, [`Duration::MAX`] if there is none
+    pub(crate) fn remaining(&self) -> Duration {
+        self.0.map_or(Duration::MAX, |deadline| {
+            deadline.saturating_duration_since(Instant::now())
+        })
+    }
+}
+
+/// Bounds the next read and write on the stream by the remaining time before the deadline
+pub(crate) fn set_timeouts(stream: &TcpStream, deadline: Deadline) -> io::Result<()> {
+    let remaining = deadline.remaining();
+    if remaining.is_zero() {
+        return Err(io::ErrorKind::TimedOut.into());
+    }
+    stream.set_read_timeout(Some(remaining))?;
+    stream.set_write_timeout(Some(remaining))
+}
+
+/// Connects to the first reachable socket address
+pub(crate) fn connect_first<A: ToSocketAddrs>(
+    address: A,
+    timeout: Duration,
+) -> Result<TcpStream, Reachability> {
+    match address.to_socket_addrs() {
+        Ok(addrs) => connect_any(addrs, timeout).map(|(stream, _address)| stream),
+        Err(_err) => Err(Reachability::Unreachable),
+    }
+}
+
+/// Connects to the first reachable socket address of a resolved list,
+/// returning the stream together with the socket address it connected to, as resolved
+pub(crate) fn connect_any<I: IntoIterator<Item = SocketAddr>>(
+    addrs: I,
+    timeout: Duration,
+) -> Result<(TcpStream, SocketAddr), Reachability> {
+    let mut outcome = Reachability::Unreachable;
+    for address in addrs {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => return Ok((stream, address)),
+            Err(err) => outcome = Reachability::from_error(&err),
+        }
+    }
+    Err(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serial_test::serial;
+    use std::net::TcpListener;
+
+    #[test]
+    #[serial]
+    fn should_report_open_and_refused_ports() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        assert_eq!(
+            Reachability::Open,
+            port_reachability(address, Duration::from_millis(100))
+        );
+
+        drop(listener);
+        assert_eq!(
+            Reachability::Refused,
+            port_reachability(address, Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn should_return_the_address_that_accepted_the_connection() {
+        let listener = TcpListener::bind("[::1]:0").unwrap();
+        let address = match listener.local_addr().unwrap() {
+            SocketAddr::V6(address) => address,
+            SocketAddr::V4(_) => unreachable!(),
+        };
+        let closed = SocketAddrV6::new(*address.ip(), crate::free_local_ipv6_port().unwrap(), 0, 0);
+
+        assert_eq!(
+            Some(address),
+            first_reachable_ipv6_addr(&[closed, address], Duration::from_millis(100))
+        );
+        assert_eq!(
+            None,
+            first_reachable_ipv6_addr(&[closed], Duration::from_millis(100))
+        );
+        assert_eq!(
+            Some(SocketAddr::V6(address)),
+            first_reachable_addr(address, Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn should_return_the_reachable_address_with_its_flow_info_and_scope_id() {
+        let listener = TcpListener::bind("[::1]:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // The kernel does not report the flow info of a connection, nor the scope id of the loopback
+        let resolved = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, port, 0x12345, 1));
+
+        assert_eq!(
+            Some(resolved),
+            first_reachable_addr(resolved, Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn should_return_the_family_of_the_connection() {
+        let listener = TcpListener::bind("[::1]:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let timeout = Duration::from_millis(100);
+        let addrs = [
+            SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+            SocketAddr::from((Ipv6Addr::LOCALHOST, port)),
+        ];
+
+        assert_eq!(
+            Some((addrs[1], Family::V6)),
+            first_reachable_addr_with_family(&addrs[..], timeout)
+        );
+        assert_eq!(
+            (Reachability::Open, Some(Family::V6)),
+            port_reachability_with_family(&addrs[..], timeout)
+        );
+
+        drop(listener);
+        assert_eq!(None, first_reachable_addr_with_family(&addrs[..], timeout));
+        assert_eq!(
+            (Reachability::Refused, None),
+            port_reachability_with_family(&addrs[..], timeout)
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn should_report_the_reachability_of_each_loopback_address() {
+        let listener = TcpListener::bind("[::1]:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let timeout = Duration::from_millis(100);
+        assert_eq!(
+            (Reachability::Refused, Reachability::Open),
+            loopback_reachability(port, timeout)
+        );
+
+        let _ipv4 = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).unwrap();
+        assert_eq!(
+            (Reachability::Open, Reachability::Open),
+            loopback_reachability(port, timeout)
+        );
+        drop(listener);
+        assert_eq!(
+            (Reachability::Open, Reachability::Refused),
+            loopback_reachability(port, timeout)
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn should_compare_local_and_external_reachability() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let timeout = Duration::from_millis(100);
+
+        assert_eq!(
+            ForwardStatus::Both,
+            compare_local_vs_external(port, "127.0.0.1", timeout)
+        );
+        if crate::is_local_ipv6_port_free(port) {
+            assert_eq!(
+                ForwardStatus::LocalOnly,
+                compare_local_vs_external(port, "::1", timeout)
+            );
+        }
+
+        drop(listener);
+        assert_eq!(
+            ForwardStatus::Neither,
+            compare_local_vs_external(port, "127.0.0.1", timeout)
+        );
+    }
+
+    #[test]
+    fn should_resolve_hosts() {
+        assert!(can_resolve("localhost"));
+        assert!(can_resolve("127.0.0.1"));
+        assert!(can_resolve("::1"));
+        assert!(!can_resolve("[::1]"));
+    }
+
+    #[test]
+    fn should_distinguish_resets_from_refusals() {
+        let reachability_of = |kind| Reachability::from_error(&io::Error::from(kind));
+        assert_eq!(
+            Reachability::Refused,
+            reachability_of(io::ErrorKind::ConnectionRefused)
+        );
+        assert_eq!(
+            Reachability::Reset,
+            reachability_of(io::ErrorKind::ConnectionReset)
+        );
+        assert_eq!(
+            Reachability::Reset,
+            reachability_of(io::ErrorKind::ConnectionAborted)
+        );
+        assert_eq!(
+            Reachability::TimedOut,
+            reachability_of(io::ErrorKind::TimedOut)
+        );
+    }
+
+    #[test]
+    fn should_report_unresolvable_addresses_as_unreachable() {
+        assert_eq!(
+            Reachability::Unreachable,
+            port_reachability("missing port", Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn a_service_bound_to_all_interfaces_should_be_reachable_locally() {
+        let listener = TcpListener::bind("0.0.0.0:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(is_service_reachable_locally(
+            port,
+            Duration::from_millis(100)
+        ));
+        if let Some(ip) = default_route_ip() {
+            assert!(crate::is_port_reachable_with_timeout(
+                (ip, port),
+                Duration::from_millis(100)
+            ));
+        }
+
+        drop(listener);
+        assert!(!is_service_reachable_locally(
+            port,
+            Duration::from_millis(100)
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn should_report_the_state_of_a_local_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert_eq!(
+            PortState::ReachableLocally,
+            port_state(port, Duration::from_millis(100))
+        );
+
+        drop(listener);
+        assert_eq!(
+            PortState::FreeLocally,
+            port_state(port, Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn should_give_up_on_a_slow_resolution() {
+        let start = std::time::Instant::now();
+        let resolved = resolve_within(
+            || {
+                thread::sleep(Duration::from_secs(2));
+                Ok(vec![SocketAddr::from((Ipv4Addr::LOCALHOST, 80))])
+            },
+            Duration::from_millis(100),
+        );
+        assert_eq!(None, resolved);
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        let address = SocketAddr::from((Ipv4Addr::LOCALHOST, 80));
+        assert_eq!(
+            Some(vec![address]),
+            resolve_within(move || Ok(vec![address]), Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn should_be_reachable_with_separate_timeouts() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let timeout = Duration::from_millis(100);
+        assert!(is_port_reachable_with_timeouts(
+            "127.0.0.1",
+            port,
+            timeout,
+            timeout
+        ));
+        let start = Instant::now();
+        assert!(!is_port_reachable_with_timeouts(
+            "missing port",
+            port,
+            timeout,
+            timeout
+        ));
+        assert!(start.elapsed() < 3 * timeout);
+
+        drop(listener);
+        assert!(!is_port_reachable_with_timeouts(
+            "127.0.0.1",
+            port,
+            timeout,
+            timeout
+        ));
+    }
+
+    #[test]
+    fn should_bound_a_slow_resolution_and_the_connections_with_a_combined_budget() {
+        let resolve_timeout = Duration::from_millis(100);
+        let connect_timeout = Duration::from_millis(100);
+        let margin = Duration::from_millis(100);
+
+        let start = Instant::now();
+        assert!(!reachable_with_timeouts(
+            || {
+                thread::sleep(Duration::from_secs(2));
+                Ok(vec![SocketAddr::from(([127, 0, 0, 1], 1))])
+            },
+            resolve_timeout,
+            connect_timeout,
+        ));
+        assert!(start.elapsed() < resolve_timeout + connect_timeout + margin);
+    }
+
+    #[test]
+    #[serial]
+    fn should_not_panic_with_timeouts_too_large_for_a_deadline() {
+        assert_eq!(Duration::MAX, Deadline::after(Duration::MAX).remaining());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        assert!(is_port_reachable_with_timeouts(
+            "127.0.0.1",
+            address.port(),
+            Duration::MAX,
+            Duration::MAX
+        ));
+        assert!(reachable_with_timeouts(
+            move || Ok(vec![address]),
+            Duration::MAX,
+            Duration::MAX
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn should_connect_after_a_resolution_within_the_budget() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let timeout = Duration::from_millis(500);
+
+        assert!(reachable_with_timeouts(
+            move || {
+                thread::sleep(Duration::from_millis(50));
+                Ok(vec![address])
+            },
+            timeout,
+            timeout,
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn should_check_the_response_to_a_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0u8; 4];
+                stream.read_exact(&mut request).unwrap();
+                // The response is split to check that it is accumulated
+                stream.write_all(b"+PO").unwrap();
+                thread::sleep(Duration::from_millis(10));
+                stream.write_all(b"NG\r\n").unwrap();
+            }
+        });
+        let timeout = Duration::from_secs(1);
+
+        assert!(is_reachable_with_exchange(
+            address,
+            b"PING",
+            |response| response == b"+PONG\r\n",
+            timeout
+        ));
+        assert!(!is_reachable_with_exchange(
+            address,
+            b"PING",
+            |response| response.starts_with(b"-ERR"),
+            timeout
+        ));
+        server.join().unwrap();
+        assert!(!is_reachable_with_exchange(
+            address,
+            b"PING",
+            |_| true,
+            timeout
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn should_measure_the_time_to_the_first_byte() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut answering, _) = listener.accept().unwrap();
+            let mut request = [0u8; 4];
+            answering.read_exact(&mut request).unwrap();
+            thread::sleep(Duration::from_millis(50));
+            answering.write_all(b"PONG").unwrap();
+            // The second connection is accepted but never answered
+            listener.accept().unwrap()
+        });
+        let timeout = Duration::from_secs(1);
+
+        let elapsed = time_to_first_byte(address, b"PING", timeout).unwrap();
+        assert!(elapsed >= Duration::from_millis(50));
+        assert!(elapsed < timeout);
+
+        let start = Instant::now();
+        let hanging_timeout = Duration::from_millis(200);
+        assert_eq!(None, time_to_first_byte(address, b"PING", hanging_timeout));
+        assert!(start.elapsed() >= hanging_timeout);
+        drop(server.join().unwrap());
+
+        assert_eq!(None, time_to_first_byte(address, b"PING", timeout));
+    }
+
+    #[test]
+    #[serial]
+    fn should_explain_the_outcome_with_the_resolved_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let timeout = Duration::from_millis(100);
+        assert_eq!(
+            format!("open — {address} accepted the connection"),
+            explain_reachability(("localhost", address.port()), timeout)
+        );
+
+        drop(listener);
+        assert_eq!(
+            format!("connection refused — nothing is listening on {address}"),
+            explain_reachability(address, timeout)
+        );
+        assert!(explain_reachability("missing port", timeout).starts_with("unresolvable — "));
+    }
+}
@@ -0,0 +1,986 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::Family;
+
+/// A localhost port kept reserved by a listener bound to it.
+/// A dual-stack reservation is held by an IPv4 listener and an IPv6 listener with the same port.
+/// The port is released when the reservation is dropped.
+#[derive(Debug)]
+pub struct ReservedPort {
+    listener: TcpListener,
+    addr: SocketAddr,
+    ipv6_sibling: Option<TcpListener>,
+}
+
+impl ReservedPort {
+    /// Keeps the port of a bound listener reserved
+    pub fn new(listener: TcpListener) -> io::Result<Self> {
+        let addr = listener.local_addr()?;
+        Ok(ReservedPort {
+            listener,
+            addr,
+            ipv6_sibling: None,
+        })
+    }
+
+    /// Keeps a port reserved for both IPv4 and IPv6
+    fn dual_stack(ipv4: TcpListener, ipv6: TcpListener) -> io::Result<Self> {
+        let mut reserved = ReservedPort::new(ipv4)?;
+        reserved.ipv6_sibling = Some(ipv6);
+        Ok(reserved)
+    }
+
+    /// Returns the reserved port
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// Returns the reserved address, the IPv4 one for a dual-stack reservation
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns a URL with the scheme and the reserved address, e.g. `http://127.0.0.1:49231`
+    /// or `http://[::1]:49231`, to write it in a configuration file
+    pub fn to_url(&self, scheme: &str) -> String {
+        format!("{scheme}://{}", self.addr)
+    }
+
+    /// Returns the IP version of the reserved address, [`Family::Both`] for a dual-stack reservation
+    pub fn family(&self) -> Family {
+        match self.ipv6_sibling {
+            Some(_) => Family::Both,
+            None => Family::of(&self.addr),
+        }
+    }
+
+    /// Returns the listener holding the reservation, the IPv4 one for a dual-stack reservation
+    pub fn listener(&self) -> &TcpListener {
+        &self.listener
+    }
+
+    /// Returns the listener holding the reservation, so it can be used to accept connections.
+    /// For a dual-stack reservation this is the IPv4 listener, and the IPv6 one is released.
+    pub fn into_listener(self) -> TcpListener {
+        self.listener
+    }
+
+    /// Releases the port setting `SO_LINGER` to zero before closing the listener.
+    ///
+    /// With a zero linger time, closing the socket resets the connections still waiting in the
+    /// accept queue instead of shutting them down gracefully, so no TIME_WAIT state is left behind
+    /// on this side and the port can be bound again immediately.
+    /// The tradeoff is that any byte in flight on those connections is lost.
+    /// Connections already accepted from the listener are not affected and must be closed separately.
+    #[cfg(feature = "socket2")]
+    pub fn close_hard(self) -> io::Result<()> {
+        self.set_zero_linger()
+    }
+
+    /// Sets `SO_LINGER` to zero on the listeners, so they are closed hard when dropped
+    #[cfg(feature = "socket2")]
+    fn set_zero_linger(&self) -> io::Result<()> {
+        for listener in std::iter::once(&self.listener).chain(&self.ipv6_sibling) {
+            socket2::SockRef::from(listener).set_linger(Some(std::time::Duration::ZERO))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl std::os::fd::AsRawFd for ReservedPort {
+    /// Returns the file descriptor of the listener holding the reservation,
+    /// the IPv4 one for a dual-stack reservation
+    fn as_raw_fd(&self) -> std::os::fd::RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+#[cfg(windows)]
+impl std::os::windows::io::AsRawSocket for ReservedPort {
+    /// Returns the socket of the listener holding the reservation,
+    /// the IPv4 one for a dual-stack reservation
+    fn as_raw_socket(&self) -> std::os::windows::io::RawSocket {
+        self.listener.as_raw_socket()
+    }
+}
+
+/// A reservation that logs its port and how long it was held when it is released,
+/// to track down tests that release a port too early.
+///
+/// The reservation and its release are logged at the debug level through the `log` crate.
+#[cfg(feature = "log")]
+#[derive(Debug)]
+pub struct DebugReservedPort {
+    reserved: ReservedPort,
+    reserved_at: std::time::Instant,
+}
+
+#[cfg(feature = "log")]
+impl DebugReservedPort {
+    /// Starts logging the lifecycle of a reservation
+    pub fn new(reserved: ReservedPort) -> Self {
+        log::debug!("reserved port {}", reserved.port());
+        DebugReservedPort {
+            reserved,
+            reserved_at: std::time::Instant::now(),
+        }
+    }
+
+    /// Reserves an available localhost port for IPv4 and logs its lifecycle
+    pub fn reserve() -> io::Result<Self> {
+        reserve_local_ipv4_port().map(DebugReservedPort::new)
+    }
+
+    /// Returns the reserved port
+    pub fn port(&self) -> u16 {
+        self.reserved.port()
+    }
+
+    /// Returns the reservation
+    pub fn reserved(&self) -> &ReservedPort {
+        &self.reserved
+    }
+}
+
+#[cfg(feature = "log")]
+impl Drop for DebugReservedPort {
+    fn drop(&mut self) {
+        log::debug!(
+            "released port {} after {:?}",
+            self.reserved.port(),
+            self.reserved_at.elapsed()
+        );
+    }
+}
+
+/// A localhost port that was just verified to be free and is kept reserved by a listener.
+///
+/// It can only be obtained from functions that confirmed the availability of the port,
+/// so code that needs a free port can require this type instead of trusting a `bool`
+/// that may already be outdated. The port is released when the value is dropped.
+#[derive(Debug)]
+pub struct VerifiedFreePort(ReservedPort);
+
+impl VerifiedFreePort {
+    /// Returns the port number
+    pub fn as_u16(&self) -> u16 {
+        self.0.port()
+    }
+
+    /// Returns the listener holding the reservation
+    pub fn into_listener(self) -> TcpListener {
+        self.0.into_listener()
+    }
+}
+
+/// Verifies that a port is available on the localhost for IPv4 and keeps it reserved
+pub fn verify_local_port_free(port: u16) -> Option<VerifiedFreePort> {
+    reserve(PortRequest::Specific(port), Family::V4)
+        .ok()
+        .map(VerifiedFreePort)
+}
+
+/// Returns an available localhost port for IPv4, kept reserved
+pub fn verified_free_local_port() -> Option<VerifiedFreePort> {
+    reserve_local_ipv4_port().ok().map(VerifiedFreePort)
+}
+
+/// Binds a listener to an ephemeral port of the specified IP address,
+/// returning the port chosen by the OS together with the listener keeping it reserved.
+///
+/// This is the building block of all the functions returning a free port:
+/// since the port stays bound until the listener is dropped, nobody else can take it in the meantime.
+pub fn reserve_free_port_on(ip: IpAddr) -> io::Result<(u16, TcpListener)> {
+    let listener = TcpListener::bind(SocketAddr::new(ip, 0))?;
+    let port = listener.local_addr()?.port();
+    Ok((port, listener))
+}
+
+/// Returns whether the OS allows binding a listener to an ephemeral port of the IPv4 localhost,
+/// see [`check_ephemeral_allocation`]
+pub fn can_allocate_ephemeral() -> bool {
+    check_ephemeral_allocation().is_ok()
+}
+
+/// Binds a listener to an ephemeral port of the IPv4 localhost and releases it right away,
+/// returning the error of the bind if it failed.
+///
+/// In some locked-down sandboxes no port can be bound at all, so every function returning a free port
+/// fails with `None`. Calling this early allows failing fast with the actual reason instead.
+pub fn check_ephemeral_allocation() -> io::Result<()> {
+    reserve_free_port_on(Ipv4Addr::LOCALHOST.into()).map(|_reserved| ())
+}
+
+/// The localhost port wanted from [`reserve`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PortRequest {
+    /// Any available port, chosen by the OS
+    Any,
+    /// The first available port of the range, in ascending order
+    AnyIn(RangeInclusive<u16>),
+    /// Exactly this port
+    Specific(u16),
+    /// The first available port of the list, in order
+    AnyOf(Vec<u16>),
+}
+
+/// The maximum number of ephemeral ports tried by [`reserve`] for [`Family::Both`]
+const MAX_DUAL_STACK_ATTEMPTS: usize = 64;
+
+/// Reserves a localhost port matching the request for the specified IP version.
+///
+/// For [`Family::Both`] the port is reserved for both IPv4 and IPv6. With [`PortRequest::Any`],
+/// ephemeral ports that are not available for IPv6 are kept bound until the search is over,
+/// so they are not offered again; after 64 of them the search gives up.
+/// When no requested port is available, the error of the last bind attempt is returned.
+pub fn reserve(request: PortRequest, family: Family) -> io::Result<ReservedPort> {
+    match request {
+        PortRequest::Any => match family {
+            Family::V4 => ReservedPort::new(reserve_free_port_on(Ipv4Addr::LOCALHOST.into())?.1),
+            Family::V6 => ReservedPort::new(reserve_free_port_on(Ipv6Addr::LOCALHOST.into())?.1),
+            Family::Both => {
+                let mut rejected = Vec::new();
+                for _ in 0..MAX_DUAL_STACK_ATTEMPTS {
+                    let (port, ipv4) = reserve_free_port_on(Ipv4Addr::LOCALHOST.into())?;
+                    match TcpListener::bind((Ipv6Addr::LOCALHOST, port)) {
+                        Ok(ipv6) => return ReservedPort::dual_stack(ipv4, ipv6),
+                        Err(_) => rejected.push(ipv4),
+                    }
+                }
+                Err(io::ErrorKind::AddrInUse.into())
+            }
+        },
+        PortRequest::Specific(port) => reserve_specific(port, family),
+        PortRequest::AnyIn(range) => reserve_first(range, family),
+        PortRequest::AnyOf(ports) => reserve_first(ports, family),
+    }
+}
+
+fn reserve_specific(port: u16, family: Family) -> io::Result<ReservedPort> {
+    match family {
+        Family::V4 => ReservedPort::new(TcpListener::bind((Ipv4Addr::LOCALHOST, port))?),
+        Family::V6 => ReservedPort::new(TcpListener::bind((Ipv6Addr::LOCALHOST, port))?),
+        Family::Both => {
+            let ipv4 = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+            let ipv6 = TcpListener::bind((Ipv6Addr::LOCALHOST, port))?;
+            ReservedPort::dual_stack(ipv4, ipv6)
+        }
+    }
+}
+
+fn reserve_first<I: IntoIterator<Item = u16>>(
+    ports: I,
+    family: Family,
+) -> io::Result<ReservedPort> {
+    let mut last_error = io::Error::from(io::ErrorKind::AddrInUse);
+    for port in ports {
+        match reserve_specific(port, family) {
+            Ok(reserved) => return Ok(reserved),
+            Err(err) => last_error = err,
+        }
+    }
+    Err(last_error)
+}
+
+/// The maximum number of ephemeral ports rejected by [`reserve_spread_ports`] before giving up
+const MAX_SPREAD_REJECTIONS: usize = 64;
+
+/// Reserves `n` distinct localhost ports for IPv4, each one at least `min_gap` away from
+/// all the others, e.g. so that the port right after a reserved one is never reserved as well.
+///
+/// The ports are chosen by the OS; those too close to a port already reserved are kept bound
+/// until the search is over, so they are not offered again. Returns `None` if `n` ports that far
+/// apart cannot fit in the port numbers, if a bind fails, or after 64 ports that were too close.
+pub fn reserve_spread_ports(n: usize, min_gap: u16) -> Option<Vec<ReservedPort>> {
+    let span = n.saturating_sub(1).checked_mul(usize::from(min_gap))?;
+    if span > usize::from(u16::MAX) {
+        return None;
+    }
+    let mut reserved: Vec<ReservedPort> = Vec::with_capacity(n);
+    let mut rejected = Vec::new();
+    while reserved.len() < n {
+        if rejected.len() >= MAX_SPREAD_REJECTIONS {
+            return None;
+        }
+        let port = reserve_local_ipv4_port().ok()?;
+        if reserved
+            .iter()
+            .all(|other| other.port().abs_diff(port.port()) >= min_gap)
+        {
+            reserved.push(port);
+        } else {
+            rejected.push(port);
+        }
+    }
+    Some(reserved)
+}
+
+/// The maximum number of ephemeral ports tried by [`free_port_on_each`]
+const MAX_ON_EACH_ATTEMPTS: usize = 64;
+
+/// Returns a port number available on all the specified IP addresses, together with a listener
+/// bound to it on each of them, in the same order as the addresses.
+///
+/// The port is chosen by the OS on the first address and then bound on the others; ports that
+/// are not available everywhere are kept bound until the search is over, so they are not offered
+/// again. Returns `None` if no address is specified, if the first address cannot be bound, or after
+/// 64 ports that were not available on all the addresses.
+pub fn free_port_on_each(ips: &[IpAddr]) -> Option<(u16, Vec<TcpListener>)> {
+    let (first, others) = ips.split_first()?;
+    let mut rejected = Vec::new();
+    for _ in 0..MAX_ON_EACH_ATTEMPTS {
+        let (port, listener) = reserve_free_port_on(*first).ok()?;
+        let mut listeners = vec![listener];
+        for ip in others {
+            match TcpListener::bind(SocketAddr::new(*ip, port)) {
+                Ok(listener) => listeners.push(listener),
+                Err(_) => break,
+            }
+        }
+        if listeners.len() == ips.len() {
+            return Some((port, listeners));
+        }
+        rejected.push(listeners);
+    }
+    None
+}
+
+/// The delay before the first retry of [`reserve_ephemeral_retrying`], doubled at each retry
+const RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// Like [`reserve_free_port_on`] for the IPv4 localhost, retrying up to `attempts` times in total
+/// when the bind fails with an error that is likely transient on a contended machine, e.g. the
+/// process running out of file descriptors or the ephemeral ports being momentarily exhausted.
+///
+/// Other errors, such as permission errors, are returned immediately, as retrying them would not help.
+/// The delay between two attempts starts at 10 milliseconds and doubles after each retry.
+pub fn reserve_ephemeral_retrying(attempts: u32) -> io::Result<(u16, TcpListener)> {
+    let mut delay = RETRY_DELAY;
+    let mut remaining = attempts.max(1);
+    loop {
+        remaining -= 1;
+        match reserve_free_port_on(Ipv4Addr::LOCALHOST.into()) {
+            Err(err) if remaining > 0 && is_transient(&err) => {
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Returns whether a bind error is likely to go away by itself
+fn is_transient(err: &io::Error) -> bool {
+    is_fd_exhaustion(err)
+        || matches!(
+            err.kind(),
+            io::ErrorKind::Interrupted
+                | io::ErrorKind::WouldBlock
+                | io::ErrorKind::AddrInUse
+                | io::ErrorKind::AddrNotAvailable
+                | io::ErrorKind::OutOfMemory
+        )
+}
+
+/// Like [`reserve_free_port_on`] for the IPv4 localhost, with a listener that is inherited by the
+/// child processes, so a subprocess server can accept connections on it without racing for the port
+/// (as with systemd socket activation).
+///
+/// On Unix platforms this clears `FD_CLOEXEC`: the listener stays open across `exec` with the same
+/// file descriptor number, e.g. `listener.as_raw_fd()`, which has to be passed to the child, for example
+/// through an environment variable. On Windows this sets `HANDLE_FLAG_INHERIT`, so the socket is
+/// inherited by processes created with handle inheritance enabled, as `std::process::Command` does.
+/// Note that every child process spawned while the listener is open inherits it.
+#[cfg(feature = "socket2")]
+pub fn reserve_ephemeral_inheritable() -> io::Result<(u16, TcpListener)> {
+    let (port, listener) = reserve_free_port_on(Ipv4Addr::LOCALHOST.into())?;
+    set_inheritable(&listener)?;
+    Ok((port, listener))
+}
+
+#[cfg(all(feature = "socket2", unix))]
+fn set_inheritable(listener: &TcpListener) -> io::Result<()> {
+    socket2::SockRef::from(listener).set_cloexec(false)
+}
+
+#[cfg(all(feature = "socket2", windows))]
+fn set_inheritable(listener: &TcpListener) -> io::Result<()> {
+    socket2::SockRef::from(listener).set_no_inherit(false)
+}
+
+#[cfg(all(feature = "socket2", not(any(unix, windows))))]
+fn set_inheritable(_listener: &TcpListener) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Reserves an available localhost port for IPv4
+pub fn reserve_local_port() -> io::Result<ReservedPort> {
+    reserve_local_ipv4_port()
+}
+
+/// Reserves an available localhost port for IPv4
+pub fn reserve_local_ipv4_port() -> io::Result<ReservedPort> {
+    reserve(PortRequest::Any, Family::V4)
+}
+
+/// Reserves an available localhost port for IPv6
+pub fn reserve_local_ipv6_port() -> io::Result<ReservedPort> {
+    reserve(PortRequest::Any, Family::V6)
+}
+
+/// Reserves an available localhost port for IPv4 and writes its number to a file, e.g. for a script
+/// that reserves a port and hands it over to another process reading the file.
+///
+/// The number is followed by a newline. It is first written to a temporary file in the same directory,
+/// which is then renamed, so a reader never sees a partially written file. The port stays reserved
+/// until the returned value is dropped, while the file is left in place; see [`PortFile`] to remove
+/// it at the same time.
+pub fn reserve_and_write(path: &Path) -> io::Result<ReservedPort> {
+    let reserved = reserve_local_ipv4_port()?;
+    write_atomically(path, format!("{}\n", reserved.port()).as_bytes())?;
+    Ok(reserved)
+}
+
+/// Writes a file through a temporary file renamed over it, removing the temporary file on failure
+fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or(io::ErrorKind::InvalidInput)?
+        .to_string_lossy();
+    let temporary = path.with_file_name(format!(".{file_name}.{}.tmp", std::process::id()));
+    let written = fs::write(&temporary, content).and_then(|_| fs::rename(&temporary, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    written
+}
+
+/// A localhost port reserved by [`reserve_and_write`] whose file is removed when it is released
+#[derive(Debug)]
+pub struct PortFile {
+    reserved: ReservedPort,
+    path: PathBuf,
+}
+
+impl PortFile {
+    /// Reserves an available localhost port for IPv4 and writes its number to a file,
+    /// as [`reserve_and_write`] does
+    pub fn reserve(path: &Path) -> io::Result<Self> {
+        Ok(PortFile {
+            reserved: reserve_and_write(path)?,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Returns the reserved port
+    pub fn port(&self) -> u16 {
+        self.reserved.port()
+    }
+
+    /// Returns the path of the file holding the port number
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PortFile {
+    fn drop(&mut self) {
+        // The file may already have been removed by its reader
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// A set of localhost ports for IPv4 reserved in advance by [`reserve_pool`], handed out one at a time.
+///
+/// Each reserved port holds a file descriptor for as long as it is in the pool.
+/// The ports that were not taken are released when the pool is dropped.
+#[derive(Debug)]
+pub struct PortPool {
+    ports: VecDeque<ReservedPort>,
+}
+
+impl PortPool {
+    /// Takes the next reserved port out of the pool, or returns `None` if the pool is empty
+    pub fn take(&mut self) -> Option<ReservedPort> {
+        self.ports.pop_front()
+    }
+
+    /// Returns the number of reserved ports left in the pool
+    pub fn len(&self) -> usize {
+        self.ports.len()
+    }
+
+    /// Returns whether all the reserved ports were taken
+    pub fn is_empty(&self) -> bool {
+        self.ports.is_empty()
+    }
+}
+
+/// Reserves `n` distinct localhost ports for IPv4 at once, so a whole test module can draw
+/// ports from the pool without racing with other processes.
+/// Returns the error of the failed bind if the `n` ports cannot all be reserved.
+pub fn reserve_pool(n: usize) -> io::Result<PortPool> {
+    let ports = (0..n)
+        .map(|_| reserve(PortRequest::Any, Family::V4))
+        .collect::<io::Result<_>>()?;
+    Ok(PortPool { ports })
+}
+
+/// A localhost port for IPv4 that is replaced by a fresh one on request, e.g. for a test server
+/// restarted many times, each time on a new port.
+///
+/// The rolling port owns the reservation and only lends it, so it never holds more than one:
+/// [`RollingPort::next`] releases the current port before reserving the next one.
+/// The last reservation is released when the value is dropped.
+#[derive(Debug, Default)]
+pub struct RollingPort {
+    current: Option<ReservedPort>,
+}
+
+impl RollingPort {
+    /// Creates a rolling port that holds no reservation until [`RollingPort::next`] is called
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Releases the reservation previously held, reserves a new ephemeral port and returns it,
+    /// holding it until the next call.
+    ///
+    /// As the previous port is released first, the OS may occasionally hand back the same port number.
+    /// This is not an [`Iterator`], since the returned reservation borrows the rolling port.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> io::Result<&ReservedPort> {
+        self.current = None;
+        Ok(self.current.insert(reserve_local_ipv4_port()?))
+    }
+
+    /// Returns the handle to the port currently reserved, if any
+    pub fn current(&self) -> Option<&ReservedPort> {
+        self.current.as_ref()
+    }
+
+    /// Releases the handle to the port currently reserved, if any
+    pub fn release(&mut self) {
+        self.current = None;
+    }
+}
+
+/// The reason why [`free_local_ports`] could not allocate all the requested ports
+#[derive(Debug)]
+pub enum BatchError {
+    /// The process or the system ran out of file descriptors (`EMFILE` or `ENFILE`) after
+    /// allocating `allocated` ports. Raising the file descriptor limit (e.g. `ulimit -n`)
+    /// is more likely to help than waiting for ports to become free.
+    FdExhausted {
+        /// The number of ports allocated before running out of file descriptors
+        allocated: usize,
+    },
+    /// A port could not be allocated for another reason
+    Io(io::Error),
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatchError::FdExhausted { allocated } => write!(
+                f,
+                "ran out of file descriptors after allocating {allocated} ports"
+            ),
+            BatchError::Io(err) => write!(f, "failed to allocate a port: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for BatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BatchError::FdExhausted { .. } => None,
+            BatchError::Io(err) => Some(err),
+        }
+    }
+}
+
+/// Returns `n` distinct available localhost ports for IPv4.
+///
+/// All the ports are kept bound until the last one is allocated, so the OS never hands out
+/// the same port twice. Each port needs a file descriptor while the batch is allocated,
+/// so large batches can hit the file descriptor limit of the process: this is reported as
+/// [`BatchError::FdExhausted`] rather than as a lack of free ports.
+pub fn free_local_ports(n: usize) -> Result<Vec<u16>, BatchError> {
+    let mut listeners = Vec::with_capacity(n);
+    let mut ports = Vec::with_capacity(n);
+    for _ in 0..n {
+        match reserve_free_port_on(Ipv4Addr::LOCALHOST.into()) {
+            Ok((port, listener)) => {
+                ports.push(port);
+                listeners.push(listener);
+            }
+            Err(err) if is_fd_exhaustion(&err) => {
+                return Err(BatchError::FdExhausted {
+                    allocated: ports.len(),
+                })
+            }
+            Err(err) => return Err(BatchError::Io(err)),
+        }
+    }
+    Ok(ports)
+}
+
+/// Returns whether an error reports that the process (`EMFILE`) or the system (`ENFILE`)
+/// has no file descriptor left
+fn is_fd_exhaustion(err: &io::Error) -> bool {
+    // EMFILE and ENFILE have the same values on Linux, macOS and the BSDs
+    #[cfg(unix)]
+    const FD_EXHAUSTION_CODES: &[i32] = &[24, 23];
+    // WSAEMFILE
+    #[cfg(windows)]
+    const FD_EXHAUSTION_CODES: &[i32] = &[10024];
+    #[cfg(not(any(unix, windows)))]
+    const FD_EXHAUSTION_CODES: &[i32] = &[];
+
+    err.raw_os_error()
+        .is_some_and(|code| FD_EXHAUSTION_CODES.contains(&code))
+}
+
+/// Estimates how many localhost ports can be reserved at the same time, up to `limit`.
+///
+/// It binds IPv4 listeners on ephemeral ports until `limit` is reached or a bind fails,
+/// then releases all of them before returning how many succeeded. The result is only an
+/// estimate of the headroom: it is bound by both the ephemeral port range and the file
+/// descriptor limit of the process, and other processes can take ports in the meantime.
+pub fn probe_bindable_capacity(limit: usize) -> usize {
+    let listeners: Vec<ReservedPort> = (0..limit)
+        .map_while(|_| reserve_local_ipv4_port().ok())
+        .collect();
+    listeners.len()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{is_local_ipv4_port_free, is_local_ipv6_port_free};
+    use serial_test::serial;
+
+    #[test]
+    #[serial]
+    fn a_reserved_port_should_be_busy_until_dropped() {
+        let reserved = reserve_local_port().unwrap();
+        let port = reserved.port();
+        assert_eq!(Family::V4, reserved.family());
+        assert!(!is_local_ipv4_port_free(port));
+
+        drop(reserved);
+        assert!(is_local_ipv4_port_free(port));
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    #[serial]
+    fn a_debug_reservation_should_hold_the_port_until_dropped() {
+        let reserved = DebugReservedPort::reserve().unwrap();
+        let port = reserved.port();
+        assert_eq!(port, reserved.reserved().port());
+        assert!(!is_local_ipv4_port_free(port));
+
+        drop(reserved);
+        assert!(is_local_ipv4_port_free(port));
+    }
+
+    #[test]
+    #[serial]
+    fn should_format_the_reserved_address_as_a_url() {
+        let reserved = reserve_local_ipv4_port().unwrap();
+        let port = reserved.port();
+        assert_eq!(
+            SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+            reserved.local_addr()
+        );
+        assert_eq!(format!("http://127.0.0.1:{port}"), reserved.to_url("http"));
+
+        let reserved = reserve_local_ipv6_port().unwrap();
+        let port = reserved.port();
+        assert_eq!(format!("https://[::1]:{port}"), reserved.to_url("https"));
+    }
+
+    #[test]
+    #[serial]
+    fn should_reserve_an_ipv6_port() {
+        let reserved = reserve_local_ipv6_port().unwrap();
+        assert_eq!(Family::V6, reserved.family());
+        assert!(!is_local_ipv6_port_free(reserved.port()));
+        assert_eq!(
+            reserved.port(),
+            reserved.into_listener().local_addr().unwrap().port()
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn a_verified_port_should_be_reserved() {
+        let verified = verified_free_local_port().unwrap();
+        let port = verified.as_u16();
+        assert!(!is_local_ipv4_port_free(port));
+        assert!(verify_local_port_free(port).is_none());
+
+        let listener = verified.into_listener();
+        assert_eq!(port, listener.local_addr().unwrap().port());
+        drop(listener);
+        assert_eq!(port, verify_local_port_free(port).unwrap().as_u16());
+    }
+
+    #[test]
+    #[serial]
+    fn should_reserve_a_port_on_the_specified_ip() {
+        let (port, listener) = reserve_free_port_on(Ipv4Addr::UNSPECIFIED.into()).unwrap();
+        assert_eq!(
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)),
+            listener.local_addr().unwrap()
+        );
+        assert!(!is_local_ipv4_port_free(port));
+
+        let (port, listener) = reserve_free_port_on(Ipv6Addr::LOCALHOST.into()).unwrap();
+        assert_eq!(port, listener.local_addr().unwrap().port());
+        assert!(!is_local_ipv6_port_free(port));
+    }
+
+    #[test]
+    #[serial]
+    fn should_allocate_an_ephemeral_port() {
+        assert!(can_allocate_ephemeral());
+        assert!(check_ephemeral_allocation().is_ok());
+    }
+
+    #[test]
+    #[serial]
+    fn should_reserve_the_requested_port() {
+        let any = reserve(PortRequest::Any, Family::Both).unwrap();
+        let port = any.port();
+        assert_eq!(Family::Both, any.family());
+        assert!(!is_local_ipv4_port_free(port));
+        assert!(!is_local_ipv6_port_free(port));
+        assert!(reserve(PortRequest::Specific(port), Family::V6).is_err());
+        drop(any);
+
+        let specific = reserve(PortRequest::Specific(port), Family::V6).unwrap();
+        assert_eq!((port, Family::V6), (specific.port(), specific.family()));
+
+        let any_of = reserve(PortRequest::AnyOf(vec![port, port]), Family::Both);
+        assert!(any_of.is_err());
+        let any_in = reserve(PortRequest::AnyIn(port..=port), Family::V4).unwrap();
+        assert_eq!((port, Family::V4), (any_in.port(), any_in.family()));
+    }
+
+    #[test]
+    #[serial]
+    fn a_pool_should_hand_out_its_reserved_ports() {
+        let mut pool = reserve_pool(3).unwrap();
+        assert_eq!(3, pool.len());
+
+        let taken = pool.take().unwrap();
+        let other = pool.take().unwrap();
+        assert_ne!(taken.port(), other.port());
+        assert_eq!(1, pool.len());
+
+        // Taken ports stay reserved after the pool is dropped
+        drop(pool);
+        assert!(!is_local_ipv4_port_free(taken.port()));
+        let port = other.port();
+        drop(other);
+        assert!(is_local_ipv4_port_free(port));
+        assert!(reserve_pool(0).unwrap().is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn a_rolling_port_should_release_the_previous_port() {
+        let mut rolling = RollingPort::new();
+        assert!(rolling.current().is_none());
+
+        let first_port = rolling.next().unwrap().port();
+        assert_eq!(Some(first_port), rolling.current().map(ReservedPort::port));
+        assert!(!is_local_ipv4_port_free(first_port));
+
+        let second = rolling.next().unwrap().port();
+        if second != first_port {
+            assert!(is_local_ipv4_port_free(first_port));
+        }
+        assert!(!is_local_ipv4_port_free(second));
+        assert_eq!(Some(second), rolling.current().map(ReservedPort::port));
+
+        rolling.release();
+        assert!(rolling.current().is_none());
+        assert!(is_local_ipv4_port_free(second));
+    }
+
+    #[test]
+    #[serial]
+    fn should_write_the_reserved_port_to_a_file() {
+        let path = std::env::temp_dir().join(format!("port_check_{}", std::process::id()));
+        let reserved = reserve_and_write(&path).unwrap();
+        assert_eq!(
+            format!("{}\n", reserved.port()),
+            fs::read_to_string(&path).unwrap()
+        );
+        assert!(!is_local_ipv4_port_free(reserved.port()));
+        drop(reserved);
+        assert!(path.exists());
+
+        let port_file = PortFile::reserve(&path).unwrap();
+        let port = port_file.port();
+        assert_eq!(format!("{port}\n"), fs::read_to_string(&path).unwrap());
+        assert_eq!(path, port_file.path());
+        drop(port_file);
+        assert!(!path.exists());
+        assert!(is_local_ipv4_port_free(port));
+
+        assert!(reserve_and_write(&path.join("missing").join("port")).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn should_bind_the_same_port_on_each_address() {
+        let ips = [Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()];
+        let (port, listeners) = free_port_on_each(&ips).unwrap();
+        assert_eq!(2, listeners.len());
+        for (ip, listener) in ips.iter().zip(&listeners) {
+            assert_eq!(SocketAddr::new(*ip, port), listener.local_addr().unwrap());
+        }
+        assert!(!is_local_ipv4_port_free(port));
+        assert!(!is_local_ipv6_port_free(port));
+
+        assert!(free_port_on_each(&[]).is_none());
+        // The same address cannot be bound twice
+        assert!(free_port_on_each(&[ips[0], ips[0]]).is_none());
+    }
+
+    #[test]
+    #[serial]
+    fn should_reserve_ports_spread_apart() {
+        let reserved = reserve_spread_ports(4, 100).unwrap();
+        assert_eq!(4, reserved.len());
+        for (i, port) in reserved.iter().enumerate() {
+            assert!(!is_local_ipv4_port_free(port.port()));
+            for other in &reserved[i + 1..] {
+                assert!(port.port().abs_diff(other.port()) >= 100);
+            }
+        }
+
+        assert!(reserve_spread_ports(0, u16::MAX).unwrap().is_empty());
+        assert!(reserve_spread_ports(3, u16::MAX).is_none());
+    }
+
+    #[test]
+    #[serial]
+    fn should_count_the_bindable_ports_up_to_the_limit() {
+        assert_eq!(0, probe_bindable_capacity(0));
+        assert_eq!(16, probe_bindable_capacity(16));
+    }
+
+    #[test]
+    #[serial]
+    fn should_return_distinct_free_ports() {
+        let mut ports = free_local_ports(8).unwrap();
+        assert!(ports.iter().all(|port| is_local_ipv4_port_free(*port)));
+        ports.sort_unstable();
+        ports.dedup();
+        assert_eq!(8, ports.len());
+    }
+
+    #[test]
+    #[serial]
+    fn should_reserve_an_ephemeral_port_with_retries() {
+        let (port, listener) = reserve_ephemeral_retrying(3).unwrap();
+        assert_eq!(port, listener.local_addr().unwrap().port());
+        assert!(!is_local_ipv4_port_free(port));
+        assert!(reserve_ephemeral_retrying(0).is_ok());
+    }
+
+    #[test]
+    fn only_transient_errors_should_be_retried() {
+        assert!(is_transient(&io::ErrorKind::AddrNotAvailable.into()));
+        assert!(is_transient(&io::ErrorKind::Interrupted.into()));
+        assert!(!is_transient(&io::ErrorKind::PermissionDenied.into()));
+        #[cfg(unix)]
+        assert!(is_transient(&io::Error::from_raw_os_error(24)));
+    }
+
+    #[test]
+    fn should_detect_file_descriptor_exhaustion() {
+        #[cfg(unix)]
+        assert!(is_fd_exhaustion(&io::Error::from_raw_os_error(24)));
+        #[cfg(windows)]
+        assert!(is_fd_exhaustion(&io::Error::from_raw_os_error(10024)));
+        assert!(!is_fd_exhaustion(&io::ErrorKind::AddrInUse.into()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[serial]
+    fn should_expose_the_file_descriptor_of_the_reservation() {
+        use std::os::fd::AsRawFd;
+
+        let reserved = reserve_local_port().unwrap();
+        assert_eq!(reserved.listener().as_raw_fd(), reserved.as_raw_fd());
+    }
+
+    #[cfg(all(feature = "socket2", target_os = "linux"))]
+    #[test]
+    #[serial]
+    fn an_inheritable_reservation_should_not_be_closed_on_exec() {
+        use std::os::fd::AsRawFd;
+
+        let (port, listener) = reserve_ephemeral_inheritable().unwrap();
+        assert_eq!(port, listener.local_addr().unwrap().port());
+        // SAFETY: F_GETFD only reads the flags of a file descriptor owned by the listener
+        let flags = unsafe { libc::fcntl(listener.as_raw_fd(), libc::F_GETFD) };
+        assert_eq!(0, flags & libc::FD_CLOEXEC);
+
+        let (_port, listener) = reserve_free_port_on(Ipv4Addr::LOCALHOST.into()).unwrap();
+        // SAFETY: as above
+        let flags = unsafe { libc::fcntl(listener.as_raw_fd(), libc::F_GETFD) };
+        assert_eq!(libc::FD_CLOEXEC, flags & libc::FD_CLOEXEC);
+    }
+
+    #[cfg(feature = "socket2")]
+    #[test]
+    #[serial]
+    fn a_port_closed_hard_should_be_free_immediately() {
+        use std::io::{Read, Write};
+
+        let reserved = reserve_local_ipv4_port().unwrap();
+        let port = reserved.port();
+        // The handshake completes, the connection waits in the accept queue
+        let mut pending = std::net::TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        pending.write_all(b"lost").unwrap();
+
+        reserved.set_zero_linger().unwrap();
+        assert_eq!(
+            Some(std::time::Duration::ZERO),
+            socket2::SockRef::from(reserved.listener())
+                .linger()
+                .unwrap()
+        );
+        reserved.close_hard().unwrap();
+        let mut buffer = [0u8; 4];
+        assert_eq!(
+            io::ErrorKind::ConnectionReset,
+            pending.read(&mut buffer).unwrap_err().kind()
+        );
+        // Nothing is left in TIME_WAIT, so the port can be bound even without SO_REUSEADDR
+        let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        assert!(
+            crate::socket::bind_configured(address, |socket| socket.set_reuse_address(false))
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,612 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::ops::RangeBounds;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::range::inclusive_bounds;
+use crate::reachability::{connect_any, connect_first, Reachability};
+use crate::wait_until_reachable;
+
+/// The result of a port scan
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScanReport {
+    /// The outcome of the scan of each port
+    pub ports: BTreeMap<u16, Reachability>,
+    /// The totals of the scan
+    pub summary: ScanSummary,
+}
+
+/// The totals of a port scan
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScanSummary {
+    /// The number of ports that accepted the connection
+    pub open: usize,
+    /// The number of ports that refused the connection
+    pub refused: usize,
+    /// The number of ports that reset the connection
+    pub reset: usize,
+    /// The number of ports that did not answer within the timeout
+    pub timed_out: usize,
+    /// The number of ports that could not be reached
+    pub unreachable: usize,
+    /// The time spent on the whole scan
+    pub elapsed: Duration,
+}
+
+impl ScanSummary {
+    /// Adds the outcome of a single port to the totals
+    pub fn record(&mut self, reachability: Reachability) {
+        match reachability {
+            Reachability::Open => self.open += 1,
+            Reachability::Refused => self.refused += 1,
+            Reachability::Reset => self.reset += 1,
+            Reachability::TimedOut => self.timed_out += 1,
+            Reachability::Unreachable => self.unreachable += 1,
+        }
+    }
+
+    /// Returns the number of scanned ports
+    pub fn total(&self) -> usize {
+        self.open + self.refused + self.reset + self.timed_out + self.unreachable
+    }
+}
+
+/// Attempts a TCP connection to each port of a host, using up to `concurrency` parallel connections.
+/// The host is resolved once; if it cannot be resolved, all ports are reported as unreachable.
+/// `rate_limit` caps the connection attempts per second, as [`PortChecker::with_rate_limit`] does;
+/// `None` scans as fast as the concurrency allows.
+///
+/// This spawns a new pool of threads for each call; use a [`PortChecker`] to share one across scans.
+pub fn scan_ports<P: IntoIterator<Item = u16>>(
+    host: &str,
+    ports: P,
+    timeout: Duration,
+    concurrency: usize,
+    rate_limit: Option<u32>,
+) -> ScanReport {
+    PortChecker::new(concurrency, timeout)
+        .with_rate_limit(rate_limit)
+        .scan(host, ports)
+}
+
+/// Attempts a TCP connection to each port of the range in ascending order,
+/// and returns the first port that accepted it.
+/// The host is resolved once; if it cannot be resolved, `None` is returned.
+pub fn first_reachable_port_in_range<R: RangeBounds<u16>>(
+    host: &str,
+    range: R,
+    timeout: Duration,
+) -> Option<u16> {
+    let ips: Vec<SocketAddr> = (host, 0).to_socket_addrs().ok()?.collect();
+    let (start, end) = inclusive_bounds(&range);
+    (start..=end)
+        .map(|port| port as u16)
+        .find(|port| probe(&ips, *port, timeout).is_open())
+}
+
+/// Attempts a TCP connection to each target in order, e.g. a primary followed by its replicas,
+/// and returns the first target that accepted it.
+///
+/// Unlike the concurrent checks, a target is only tried once all the previous ones failed,
+/// so the priority of the list is strictly preserved. Each socket address a target resolves to
+/// is given `per_timeout`.
+pub fn first_reachable_target(
+    targets: &[(String, u16)],
+    per_timeout: Duration,
+) -> Option<(String, u16)> {
+    targets
+        .iter()
+        .find(|(host, port)| connect_first((host.as_str(), *port), per_timeout).is_ok())
+        .cloned()
+}
+
+/// Attempts a TCP connection to each target concurrently, one thread per target, and returns
+/// the time each successful connection took, or `None` for the targets that could not be reached
+/// within the timeout. The results are in the same order as the targets.
+///
+/// The time spent resolving a host name is not included in its latency.
+pub fn reachability_report(
+    targets: &[(String, u16)],
+    timeout: Duration,
+) -> Vec<(String, u16, Option<Duration>)> {
+    thread::scope(|scope| {
+        let probes: Vec<_> = targets
+            .iter()
+            .map(|(host, port)| {
+                scope.spawn(move || {
+                    let addrs: Vec<SocketAddr> =
+                        (host.as_str(), *port).to_socket_addrs().ok()?.collect();
+                    let start = Instant::now();
+                    connect_any(addrs, timeout)
+                        .ok()
+                        .map(|_connected| start.elapsed())
+                })
+            })
+            .collect();
+        targets
+            .iter()
+            .zip(probes)
+            .map(|((host, port), probe)| (host.clone(), *port, probe.join().unwrap_or(None)))
+            .collect()
+    })
+}
+
+/// Attempts a TCP connection to each target concurrently, as [`reachability_report`] does, and
+/// returns the targets sorted by ascending latency, e.g. to pick the fastest mirror.
+///
+/// The targets that could not be reached come last. The sort is stable, so targets with the same
+/// latency, and the unreachable ones, keep their original order.
+pub fn rank_by_latency(
+    targets: &[(String, u16)],
+    timeout: Duration,
+) -> Vec<(String, u16, Option<Duration>)> {
+    let mut report = reachability_report(targets, timeout);
+    report.sort_by_key(|(_host, _port, latency)| (latency.is_none(), *latency));
+    report
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+type Resolver = dyn Fn(&str, u16) -> Vec<SocketAddr> + Send + Sync;
+
+/// A port scanner owning a pool of worker threads that is reused by all its scans.
+/// The worker threads are stopped when the checker is dropped.
+pub struct PortChecker {
+    timeout: Duration,
+    jobs: Option<mpsc::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    resolver: Option<Arc<Resolver>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+impl PortChecker {
+    /// Creates a checker with `concurrency` worker threads, each connection attempt being bound by `timeout`
+    pub fn new(concurrency: usize, timeout: Duration) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..concurrency.max(1))
+            .map(|_| {
+                let receiver = receiver.clone();
+                thread::spawn(move || loop {
+                    let job = match receiver.lock() {
+                        Ok(receiver) => receiver.recv(),
+                        Err(_poisoned) => return,
+                    };
+                    match job {
+                        Ok(job) => job(),
+                        Err(_disconnected) => return,
+                    }
+                })
+            })
+            .collect();
+        PortChecker {
+            timeout,
+            jobs: Some(sender),
+            workers,
+            resolver: None,
+            rate_limiter: None,
+        }
+    }
+
+    /// Limits the scans to `probes_per_second` connection attempts per second, across all the worker threads.
+    /// Attempts are paced evenly rather than sent in bursts, to avoid tripping intrusion detection
+    /// systems or saturating the link. `None`, the default, removes the limit.
+    pub fn with_rate_limit(mut self, probes_per_second: Option<u32>) -> Self {
+        self.rate_limiter = probes_per_second
+            .filter(|rate| *rate > 0)
+            .map(|rate| Arc::new(RateLimiter::new(rate)));
+        self
+    }
+
+    /// Resolves host names with the provided closure instead of the system resolver.
+    /// The closure receives the host and the port, and returns the socket addresses to connect to;
+    /// an empty list means that the host could not be resolved. Scans call it once per scanned port.
+    pub fn with_resolver<F>(mut self, resolver: F) -> Self
+    where
+        F: Fn(&str, u16) -> Vec<SocketAddr> + Send + Sync + 'static,
+    {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Attempts a TCP connection to a port of a host and returns the reason of the outcome
+    pub fn reachability(&self, host: &str, port: u16) -> Reachability {
+        match connect_first(self.target(host, port), self.timeout) {
+            Ok(_stream) => Reachability::Open,
+            Err(reachability) => reachability,
+        }
+    }
+
+    /// Attempts a TCP connection to a port of a host and returns whether it succeeded
+    pub fn is_reachable(&self, host: &str, port: u16) -> bool {
+        self.reachability(host, port).is_open()
+    }
+
+    /// Waits until a TCP connection to a port of a host succeeds or the timeout elapses,
+    /// like [`crate::wait_until_reachable`], resolving the host with the resolver of the checker.
+    pub fn wait_until_reachable(&self, host: &str, port: u16, timeout: Duration) -> bool {
+        wait_until_reachable(self.target(host, port), timeout)
+    }
+
+    fn target<'a>(&'a self, host: &'a str, port: u16) -> Target<'a> {
+        Target {
+            resolver: self.resolver.as_deref(),
+            host,
+            port,
+        }
+    }
+
+    /// Attempts a TCP connection to each port of a host using the worker threads of the checker.
+    /// With the system resolver the host is resolved once, while a custom resolver is called once
+    /// per scanned port, with that port. The ports for which the host cannot be resolved are
+    /// reported as unreachable.
+    pub fn scan<P: IntoIterator<Item = u16>>(&self, host: &str, ports: P) -> ScanReport {
+        let start = Instant::now();
+        let resolve = |port| -> Arc<Vec<SocketAddr>> {
+            Arc::new(
+                self.target(host, port)
+                    .to_socket_addrs()
+                    .into_iter()
+                    .flatten()
+                    .collect(),
+            )
+        };
+        let system_ips = match self.resolver {
+            Some(_) => None,
+            None => Some(resolve(0)),
+        };
+        let (sender, receiver) = mpsc::channel();
+
+        let mut submitted = 0;
+        if let Some(jobs) = &self.jobs {
+            for port in ports {
+                let ips = match &system_ips {
+                    Some(ips) => ips.clone(),
+                    None => resolve(port),
+                };
+                let sender = sender.clone();
+                let timeout = self.timeout;
+                let rate_limiter = self.rate_limiter.clone();
+                let job: Job = Box::new(move || {
+                    if let Some(rate_limiter) = rate_limiter {
+                        rate_limiter.acquire();
+                    }
+                    let _ = sender.send((port, probe(&ips, port, timeout)));
+                });
+                if jobs.send(job).is_ok() {
+                    submitted += 1;
+                }
+            }
+        }
+        drop(sender);
+
+        let mut report = ScanReport {
+            ports: BTreeMap::new(),
+            summary: ScanSummary::default(),
+        };
+        for (port, reachability) in receiver.iter().take(submitted) {
+            report.summary.record(reachability);
+            report.ports.insert(port, reachability);
+        }
+        report.summary.elapsed = start.elapsed();
+        report
+    }
+}
+
+/// A token bucket holding a single token, so that consecutive acquisitions are evenly spaced
+struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(per_second: u32) -> Self {
+        RateLimiter {
+            interval: Duration::from_secs(1) / per_second,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Blocks until the next token is available and takes it
+    fn acquire(&self) {
+        let slot = {
+            let mut next = self
+                .next
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let slot = (*next).max(Instant::now());
+            *next = slot + self.interval;
+            slot
+        };
+        thread::sleep(slot.saturating_duration_since(Instant::now()));
+    }
+}
+
+/// A host and port resolved by the resolver of a checker, if any
+struct Target<'a> {
+    resolver: Option<&'a Resolver>,
+    host: &'a str,
+    port: u16,
+}
+
+impl ToSocketAddrs for Target<'_> {
+    type Iter = std::vec::IntoIter<SocketAddr>;
+
+    fn to_socket_addrs(&self) -> io::Result<Self::Iter> {
+        let addrs = match self.resolver {
+            Some(resolver) => resolver(self.host, self.port),
+            None => (self.host, self.port).to_socket_addrs()?.collect(),
+        };
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} could not be resolved", self.host),
+            ));
+        }
+        Ok(addrs.into_iter())
+    }
+}
+
+impl Drop for PortChecker {
+    fn drop(&mut self) {
+        // Closing the channel makes the workers return once the queued jobs are done
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+pub(crate) fn probe(ips: &[SocketAddr], port: u16, timeout: Duration) -> Reachability {
+    // Only the port is replaced, so the scope id of link-local IPv6 addresses is kept
+    let addrs = ips.iter().map(|ip| {
+        let mut addr = *ip;
+        addr.set_port(port);
+        addr
+    });
+    match connect_any(addrs, timeout) {
+        Ok(_connected) => Reachability::Open,
+        Err(reachability) => reachability,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serial_test::serial;
+    use std::net::TcpListener;
+
+    #[test]
+    #[serial]
+    fn should_scan_open_and_closed_ports() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open_port = listener.local_addr().unwrap().port();
+        let closed_port = crate::free_local_ipv4_port().unwrap();
+
+        let report = scan_ports(
+            "127.0.0.1",
+            [open_port, closed_port],
+            Duration::from_millis(100),
+            2,
+            None,
+        );
+
+        assert_eq!(Some(&Reachability::Open), report.ports.get(&open_port));
+        assert_eq!(Some(&Reachability::Refused), report.ports.get(&closed_port));
+        assert_eq!(1, report.summary.open);
+        assert_eq!(1, report.summary.refused);
+        assert_eq!(2, report.summary.total());
+    }
+
+    #[test]
+    #[serial]
+    fn should_report_the_latency_of_each_target_in_order() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open_port = listener.local_addr().unwrap().port();
+        let closed_port = crate::free_local_ipv4_port().unwrap();
+        let targets = [
+            ("127.0.0.1".to_string(), closed_port),
+            ("127.0.0.1".to_string(), open_port),
+            ("missing port".to_string(), open_port),
+        ];
+
+        let report = reachability_report(&targets, Duration::from_millis(100));
+        assert_eq!(3, report.len());
+        assert_eq!(("127.0.0.1".to_string(), closed_port, None), report[0]);
+        assert_eq!(open_port, report[1].1);
+        assert!(report[1].2.unwrap() < Duration::from_millis(100));
+        assert_eq!(None, report[2].2);
+    }
+
+    #[test]
+    #[serial]
+    fn should_rank_the_targets_by_latency() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open_port = listener.local_addr().unwrap().port();
+        let closed_port = crate::free_local_ipv4_port().unwrap();
+        let targets = [
+            ("127.0.0.1".to_string(), closed_port),
+            ("missing port".to_string(), open_port),
+            ("127.0.0.1".to_string(), open_port),
+        ];
+
+        let ranking = rank_by_latency(&targets, Duration::from_millis(100));
+        assert_eq!(3, ranking.len());
+        assert_eq!(open_port, ranking[0].1);
+        assert!(ranking[0].2.is_some());
+        assert_eq!(("127.0.0.1".to_string(), closed_port, None), ranking[1]);
+        assert_eq!(("missing port".to_string(), open_port, None), ranking[2]);
+    }
+
+    #[test]
+    #[serial]
+    fn a_checker_should_be_reusable_across_scans() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open_port = listener.local_addr().unwrap().port();
+        let checker = PortChecker::new(4, Duration::from_millis(100));
+
+        for _ in 0..3 {
+            let report = checker.scan("127.0.0.1", [open_port]);
+            assert_eq!(Some(&Reachability::Open), report.ports.get(&open_port));
+            assert_eq!(1, report.summary.total());
+        }
+        assert_eq!(4, checker.workers.len());
+    }
+
+    #[test]
+    #[serial]
+    fn should_return_the_first_reachable_port_of_the_range() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open_port = listener.local_addr().unwrap().port();
+        let timeout = Duration::from_millis(100);
+
+        assert_eq!(
+            Some(open_port),
+            first_reachable_port_in_range("127.0.0.1", open_port..=open_port, timeout)
+        );
+        assert_eq!(
+            None,
+            first_reachable_port_in_range("127.0.0.1", open_port..open_port, timeout)
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn should_return_the_first_reachable_target_in_order() {
+        let primary = TcpListener::bind("127.0.0.1:0").unwrap();
+        let replica = TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed_port = crate::free_local_ipv4_port().unwrap();
+        let targets = [
+            ("127.0.0.1".to_string(), closed_port),
+            (
+                "127.0.0.1".to_string(),
+                primary.local_addr().unwrap().port(),
+            ),
+            (
+                "127.0.0.1".to_string(),
+                replica.local_addr().unwrap().port(),
+            ),
+        ];
+        let timeout = Duration::from_millis(100);
+
+        assert_eq!(
+            Some(targets[1].clone()),
+            first_reachable_target(&targets, timeout)
+        );
+        drop(primary);
+        assert_eq!(
+            Some(targets[2].clone()),
+            first_reachable_target(&targets, timeout)
+        );
+        drop(replica);
+        assert_eq!(None, first_reachable_target(&targets, timeout));
+        assert_eq!(None, first_reachable_target(&[], timeout));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn a_report_should_be_serializable() {
+        let mut summary = ScanSummary::default();
+        summary.record(Reachability::Open);
+        summary.record(Reachability::TimedOut);
+        let report = ScanReport {
+            ports: BTreeMap::from([(22, Reachability::Open), (80, Reachability::TimedOut)]),
+            summary,
+        };
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!("open", json["ports"]["22"]);
+        assert_eq!("timed_out", json["ports"]["80"]);
+        assert_eq!(1, json["summary"]["open"]);
+        assert_eq!(1, json["summary"]["timed_out"]);
+
+        let deserialized: ScanReport = serde_json::from_value(json).unwrap();
+        assert_eq!(report, deserialized);
+    }
+
+    #[test]
+    #[serial]
+    fn a_checker_should_use_the_custom_resolver() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open_port = listener.local_addr().unwrap().port();
+        let checker = PortChecker::new(1, Duration::from_millis(100)).with_resolver(
+            |host, port| match host {
+                "service" => vec![SocketAddr::from(([127, 0, 0, 1], port))],
+                _ => vec![],
+            },
+        );
+
+        assert!(checker.is_reachable("service", open_port));
+        assert!(checker.wait_until_reachable("service", open_port, Duration::from_secs(1)));
+        assert_eq!(
+            Reachability::Unreachable,
+            checker.reachability("localhost", open_port)
+        );
+        assert_eq!(
+            Some(&Reachability::Open),
+            checker.scan("service", [open_port]).ports.get(&open_port)
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn a_checker_should_call_the_custom_resolver_with_each_scanned_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open_port = listener.local_addr().unwrap().port();
+        let other_port = crate::free_local_ipv4_port().unwrap();
+        let checker = PortChecker::new(2, Duration::from_millis(100)).with_resolver(
+            move |host, port| match (host, port) {
+                ("service", port) if port == open_port => {
+                    vec![SocketAddr::from(([127, 0, 0, 1], port))]
+                }
+                _ => vec![],
+            },
+        );
+
+        let report = checker.scan("service", [open_port, other_port]);
+        assert_eq!(Some(&Reachability::Open), report.ports.get(&open_port));
+        assert_eq!(
+            Some(&Reachability::Unreachable),
+            report.ports.get(&other_port)
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn a_rate_limited_checker_should_pace_the_probes() {
+        let closed_port = crate::free_local_ipv4_port().unwrap();
+        let checker = PortChecker::new(4, Duration::from_millis(100)).with_rate_limit(Some(20));
+
+        let report = checker.scan("127.0.0.1", [closed_port; 5]);
+        // The first probe starts immediately, the following ones 50 ms apart
+        assert!(report.summary.elapsed >= Duration::from_millis(190));
+    }
+
+    #[test]
+    #[serial]
+    fn a_rate_limited_scan_should_pace_the_probes() {
+        let closed_port = crate::free_local_ipv4_port().unwrap();
+
+        let report = scan_ports(
+            "127.0.0.1",
+            [closed_port; 5],
+            Duration::from_millis(100),
+            4,
+            Some(20),
+        );
+        assert_eq!(5, report.summary.refused);
+        assert!(report.summary.elapsed >= Duration::from_millis(190));
+    }
+
+    #[test]
+    fn unresolvable_hosts_should_be_unreachable() {
+        let report = scan_ports("", 1..=3, Duration::from_millis(100), 2, None);
+        assert_eq!(3, report.summary.unreachable);
+    }
+}
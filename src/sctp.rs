@@ -0,0 +1,54 @@
+//! Reachability checks of SCTP services, built on [`socket2`].
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// The protocol number of SCTP, the same on Linux and FreeBSD
+const IPPROTO_SCTP: i32 = 132;
+
+/// Attempts an SCTP association with an address and returns whether it was established.
+///
+/// This uses a one-to-one style SCTP socket, the SCTP counterpart of a TCP connection.
+/// SCTP must be supported by the kernel (on Linux, the `sctp` module must be loaded):
+/// otherwise the socket cannot be created and `false` is returned.
+pub fn is_sctp_port_reachable(address: SocketAddr, timeout: Duration) -> bool {
+    sctp_socket(address)
+        .and_then(|socket| socket.connect_timeout(&address.into(), timeout))
+        .is_ok()
+}
+
+fn sctp_socket(address: SocketAddr) -> std::io::Result<Socket> {
+    Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::from(IPPROTO_SCTP)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serial_test::serial;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    #[serial]
+    fn a_listening_sctp_port_should_be_reachable() {
+        let any = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+        let Ok(listener) = sctp_socket(any) else {
+            // SCTP is not supported by this kernel
+            assert!(!is_sctp_port_reachable(any, Duration::from_millis(100)));
+            return;
+        };
+        listener.bind(&any.into()).unwrap();
+        listener.listen(1).unwrap();
+        let address = listener.local_addr().unwrap().as_socket().unwrap();
+        assert!(is_sctp_port_reachable(address, Duration::from_millis(100)));
+
+        drop(listener);
+        assert!(!is_sctp_port_reachable(address, Duration::from_millis(100)));
+    }
+}
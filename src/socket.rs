@@ -0,0 +1,409 @@
+//! Checks built on [`socket2`], for full control over the socket options.
+
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+
+use socket2::{Domain, Socket, Type};
+
+use crate::reachability::Deadline;
+use crate::Port;
+
+/// Returns whether a port is available on the localhost, binding a socket configured by the provided closure.
+///
+/// The closure is called after the socket is created and before it is bound, so it can set any option
+/// (e.g. `SO_REUSEADDR`, `SO_LINGER`, TTL). As with [`crate::is_local_port_free`], `SO_REUSEADDR` is
+/// enabled by default on Unix platforms; the closure can disable it.
+/// If the closure returns an error, the port is reported as not free.
+/// The closure runs once per bound socket: once for a [`Port::Ipv4`] or [`Port::Ipv6`], and once per
+/// family for a [`Port::Both`], first for the IPv4 socket and then for the IPv6 one. This is why it
+/// is an `FnMut` rather than an `FnOnce`. A [`Port::Both`] is free only if it is free for both families.
+pub fn is_local_port_free_configured<P, F>(port: P, mut configure: F) -> bool
+where
+    P: Into<Port>,
+    F: FnMut(&Socket) -> io::Result<()>,
+{
+    match port.into() {
+        Port::Ipv4(port) => bind_configured((Ipv4Addr::LOCALHOST, port).into(), configure).is_ok(),
+        Port::Ipv6(port) => bind_configured((Ipv6Addr::LOCALHOST, port).into(), configure).is_ok(),
+        Port::Both(port) => {
+            let ipv4 = bind_configured((Ipv4Addr::LOCALHOST, port).into(), &mut configure);
+            // The IPv4 socket is kept bound until the IPv6 one is
+            ipv4.is_ok()
+                && bind_configured((Ipv6Addr::LOCALHOST, port).into(), &mut configure).is_ok()
+        }
+    }
+}
+
+/// Which combinations of socket options allow binding a listener to an address, see [`bind_report`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BindReport {
+    /// Whether a socket without any option can be bound
+    pub plain: bool,
+    /// Whether a socket with `SO_REUSEADDR` can be bound
+    pub reuse_address: bool,
+    /// Whether a socket with `SO_REUSEPORT` can be bound, `None` where the option is not supported
+    pub reuse_port: Option<bool>,
+}
+
+/// Tries to bind a listener to exactly the specified IP address and port with different socket
+/// options, and reports which attempts succeeded.
+///
+/// This explains why a server may bind a port that a free check reports as busy, or the other way
+/// around: e.g. a port with connections in TIME_WAIT can be bound with `SO_REUSEADDR` only, and
+/// a port bound by listeners with `SO_REUSEPORT` can be shared by another one with the same option.
+/// Each attempt is released before the next one.
+pub fn bind_report(ip: IpAddr, port: u16) -> BindReport {
+    let address = SocketAddr::new(ip, port);
+    // Separate statements, so each socket is closed before the next attempt
+    let plain = bind_configured(address, |socket| socket.set_reuse_address(false)).is_ok();
+    let reuse_address = bind_configured(address, |socket| socket.set_reuse_address(true)).is_ok();
+    let reuse_port = reuse_port_bindable(address);
+    BindReport {
+        plain,
+        reuse_address,
+        reuse_port,
+    }
+}
+
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+fn reuse_port_bindable(address: SocketAddr) -> Option<bool> {
+    Some(
+        bind_configured(address, |socket| {
+            socket.set_reuse_address(false)?;
+            socket.set_reuse_port(true)
+        })
+        .is_ok(),
+    )
+}
+
+#[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+fn reuse_port_bindable(_address: SocketAddr) -> Option<bool> {
+    None
+}
+
+/// Attempts a TCP connection to an address from a socket created and configured by the caller,
+/// and returns whether it succeeded.
+///
+/// This covers any need for control over the connecting socket, e.g. binding a source address
+/// or a device, or setting the TTL: the socket only has to be a TCP socket of the same IP
+/// version as the address, not connected yet. The socket is consumed, since a socket can
+/// attempt a connection only once: it is closed when the check completes, whatever the outcome.
+pub fn is_reachable_with_socket(socket: Socket, address: SocketAddr, timeout: Duration) -> bool {
+    socket.connect_timeout(&address.into(), timeout).is_ok()
+}
+
+/// Attempts a TCP connection to an address from the specified local port, e.g. to test firewall
+/// rules matching the source port, and returns whether it succeeded.
+///
+/// The connecting socket is bound to the unspecified address of the IP version of the target
+/// (`0.0.0.0` or `::`) with the local port. On Unix platforms `SO_REUSEADDR` is set, so that
+/// connections from the same port left in TIME_WAIT by previous checks do not prevent the bind.
+/// If the local port cannot be bound, e.g. because a listener is using it, the error of the bind
+/// is returned instead of `Ok(false)`. When the address resolves to several socket addresses,
+/// they are tried in order, each one with its own timeout.
+pub fn is_port_reachable_from_source_port<A: ToSocketAddrs>(
+    local_port: u16,
+    address: A,
+    timeout: Duration,
+) -> io::Result<bool> {
+    let Ok(addrs) = address.to_socket_addrs() else {
+        return Ok(false);
+    };
+    for address in addrs {
+        let socket = Socket::new(Domain::for_address(address), Type::STREAM, None)?;
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+        let local_ip: IpAddr = match address {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        socket.bind(&SocketAddr::new(local_ip, local_port).into())?;
+        if socket.connect_timeout(&address.into(), timeout).is_ok() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Attempts a TCP connection to an address with TCP Fast Open, sending `payload` in the SYN,
+/// and returns whether the connection succeeded and the payload was accepted by the OS.
+///
+/// Fast Open is only requested on Linux, where it saves a round trip against services that
+/// support it. Elsewhere, or if the OS refuses the option, this falls back to a plain connection
+/// followed by the payload, so the outcome is the same with one more round trip.
+/// See [`probe_tfo`] to know whether Fast Open was actually used.
+/// An empty payload gives a plain connection check, since Fast Open needs data to send.
+/// When the address resolves to several socket addresses, they are tried in order,
+/// each one with its own timeout.
+pub fn is_port_reachable_tfo<A: ToSocketAddrs>(
+    address: A,
+    payload: &[u8],
+    timeout: Duration,
+) -> bool {
+    probe_tfo(address, payload, timeout).is_some()
+}
+
+/// The outcome of a successful connection attempt with TCP Fast Open, see [`probe_tfo`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TfoProbe {
+    /// The socket address that accepted the connection
+    pub address: SocketAddr,
+    /// Whether the peer acknowledged the payload carried by the SYN, as reported by `TCP_INFO`
+    pub tfo_used: bool,
+}
+
+/// Like [`is_port_reachable_tfo`], but reports whether Fast Open worked end to end.
+///
+/// Returns `None` if the connection failed or the payload was not accepted by the OS.
+/// A `tfo_used` of `false` means the service is reachable but Fast Open was not used, e.g.
+/// because the peer does not support it or no Fast Open cookie was cached for it yet: the payload
+/// was then sent after the handshake. It is always `false` outside Linux or with an empty payload.
+pub fn probe_tfo<A: ToSocketAddrs>(
+    address: A,
+    payload: &[u8],
+    timeout: Duration,
+) -> Option<TfoProbe> {
+    address.to_socket_addrs().ok()?.find_map(|address| {
+        connect_tfo(address, payload, timeout)
+            .ok()
+            .map(|tfo_used| TfoProbe { address, tfo_used })
+    })
+}
+
+fn connect_tfo(address: SocketAddr, payload: &[u8], timeout: Duration) -> io::Result<bool> {
+    let deadline = Deadline::after(timeout);
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, None)?;
+    // Without Fast Open the payload is simply sent after the handshake
+    let fast_open = !payload.is_empty() && enable_fast_open_connect(&socket).is_ok();
+    // With Fast Open the connection is deferred: the SYN leaves with the first write
+    socket.connect_timeout(&address.into(), timeout)?;
+    let remaining = deadline.remaining();
+    if remaining.is_zero() {
+        return Err(io::ErrorKind::TimedOut.into());
+    }
+    socket.set_write_timeout(Some(remaining))?;
+    (&socket).write_all(payload)?;
+    Ok(fast_open && syn_data_acked(&socket))
+}
+
+#[cfg(target_os = "linux")]
+fn syn_data_acked(socket: &Socket) -> bool {
+    crate::tcp_info::syn_data_acked(socket).unwrap_or(false)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn syn_data_acked(_socket: &Socket) -> bool {
+    false
+}
+
+#[cfg(target_os = "linux")]
+fn enable_fast_open_connect(socket: &Socket) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let enabled: libc::c_int = 1;
+    // SAFETY: the pointer references a live c_int and its length, as setsockopt expects
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_FASTOPEN_CONNECT,
+            &enabled as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn enable_fast_open_connect(_socket: &Socket) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Creates a TCP socket, applies the configuration and starts listening on the address
+pub(crate) fn bind_configured<F>(address: SocketAddr, configure: F) -> io::Result<Socket>
+where
+    F: FnOnce(&Socket) -> io::Result<()>,
+{
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, None)?;
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    configure(&socket)?;
+    socket.bind(&address.into())?;
+    socket.listen(128)?;
+    Ok(socket)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serial_test::serial;
+    use std::net::TcpListener;
+
+    #[test]
+    #[serial]
+    fn should_apply_the_configuration_before_binding() {
+        let port = crate::free_local_ipv4_port().unwrap();
+
+        let mut configured = false;
+        assert!(is_local_port_free_configured(port, |socket| {
+            configured = true;
+            socket.set_ttl_v4(32)
+        }));
+        assert!(configured);
+
+        let _listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).unwrap();
+        assert!(!is_local_port_free_configured(port, |_| Ok(())));
+    }
+
+    #[test]
+    #[serial]
+    fn a_dual_stack_port_should_configure_both_sockets() {
+        let port = crate::free_local_port_family(crate::Family::Both).unwrap();
+
+        let mut configured = 0;
+        assert!(is_local_port_free_configured(Port::both(port), |_| {
+            configured += 1;
+            Ok(())
+        }));
+        assert_eq!(2, configured);
+
+        let _listener = TcpListener::bind((Ipv6Addr::LOCALHOST, port)).unwrap();
+        assert!(is_local_port_free_configured(Port::ipv4(port), |_| Ok(())));
+        assert!(!is_local_port_free_configured(Port::both(port), |_| Ok(())));
+    }
+
+    #[test]
+    #[serial]
+    fn should_report_the_bind_options_that_succeed() {
+        let port = crate::free_local_ipv4_port().unwrap();
+        let ip = IpAddr::from(Ipv4Addr::LOCALHOST);
+        let report = bind_report(ip, port);
+        assert!(report.plain);
+        assert!(report.reuse_address);
+        #[cfg(target_os = "linux")]
+        assert_eq!(Some(true), report.reuse_port);
+
+        let listener = TcpListener::bind((ip, port)).unwrap();
+        let report = bind_report(ip, port);
+        assert!(!report.plain);
+        assert!(!report.reuse_address);
+        #[cfg(target_os = "linux")]
+        assert_eq!(Some(false), report.reuse_port);
+        drop(listener);
+
+        #[cfg(target_os = "linux")]
+        {
+            let _shared = bind_configured(SocketAddr::new(ip, port), |socket| {
+                socket.set_reuse_port(true)
+            })
+            .unwrap();
+            let report = bind_report(ip, port);
+            assert!(!report.plain);
+            assert_eq!(Some(true), report.reuse_port);
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn should_connect_from_the_provided_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let timeout = Duration::from_millis(100);
+
+        let socket = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        socket
+            .bind(&SocketAddr::from((Ipv4Addr::LOCALHOST, 0)).into())
+            .unwrap();
+        assert!(is_reachable_with_socket(socket, address, timeout));
+
+        // A socket of the other IP version cannot connect
+        let socket = Socket::new(Domain::IPV6, Type::STREAM, None).unwrap();
+        assert!(!is_reachable_with_socket(socket, address, timeout));
+
+        drop(listener);
+        let socket = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        assert!(!is_reachable_with_socket(socket, address, timeout));
+    }
+
+    #[test]
+    #[serial]
+    fn should_send_the_payload_with_fast_open() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let timeout = Duration::from_millis(200);
+
+        assert!(is_port_reachable_tfo(address, b"ping", timeout));
+        let (mut server, _) = listener.accept().unwrap();
+        let mut received = [0u8; 4];
+        std::io::Read::read_exact(&mut server, &mut received).unwrap();
+        assert_eq!(*b"ping", received);
+        assert!(is_port_reachable_tfo(address, b"", timeout));
+
+        drop(server);
+        drop(listener);
+        assert!(!is_port_reachable_tfo(address, b"ping", timeout));
+        assert!(!is_port_reachable_tfo(address, b"", timeout));
+    }
+
+    #[test]
+    #[serial]
+    fn should_report_whether_fast_open_was_used() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let timeout = Duration::from_millis(200);
+
+        // Whether Fast Open is used with a payload depends on the system settings and the cached cookies
+        let probe = probe_tfo(address, b"ping", timeout).unwrap();
+        assert_eq!(address, probe.address);
+        assert_eq!(
+            Some(TfoProbe {
+                address,
+                tfo_used: false
+            }),
+            probe_tfo(address, b"", timeout)
+        );
+
+        drop(listener);
+        assert_eq!(None, probe_tfo(address, b"ping", timeout));
+    }
+
+    #[test]
+    #[serial]
+    fn a_configuration_error_should_report_the_port_as_not_free() {
+        let port = crate::free_local_ipv6_port().unwrap();
+        assert!(!is_local_port_free_configured(Port::ipv6(port), |_| {
+            Err(io::Error::other("configuration failed"))
+        }));
+    }
+
+    #[test]
+    #[serial]
+    fn should_connect_from_the_source_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let source_port = crate::free_local_ipv4_port().unwrap();
+        let timeout = Duration::from_millis(100);
+
+        assert!(is_port_reachable_from_source_port(source_port, address, timeout).unwrap());
+        let (_server, client) = listener.accept().unwrap();
+        assert_eq!(source_port, client.port());
+
+        let _busy = TcpListener::bind((Ipv4Addr::UNSPECIFIED, source_port)).unwrap();
+        assert_eq!(
+            io::ErrorKind::AddrInUse,
+            is_port_reachable_from_source_port(source_port, address, timeout)
+                .unwrap_err()
+                .kind()
+        );
+
+        drop(listener);
+        let source_port = crate::free_local_ipv4_port().unwrap();
+        assert!(!is_port_reachable_from_source_port(source_port, address, timeout).unwrap());
+    }
+}
@@ -0,0 +1,185 @@
+//! Parsing of `host:port` targets, as typed on a command line or found in a configuration file.
+
+use std::fmt;
+use std::net::Ipv6Addr;
+use std::time::Duration;
+
+use crate::is_port_reachable_with_timeout;
+
+/// The reason why a target could not be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The target is empty
+    Empty,
+    /// The target has no host before the port
+    MissingHost,
+    /// The target has no port and no default port was provided
+    MissingPort,
+    /// The port is not a number between 1 and 65535
+    InvalidPort(String),
+    /// The host is not valid, e.g. an IPv6 address with a missing bracket
+    InvalidHost(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "the target is empty"),
+            ParseError::MissingHost => write!(f, "the target has no host"),
+            ParseError::MissingPort => write!(f, "the target has no port"),
+            ParseError::InvalidPort(port) => write!(f, "invalid port: {port}"),
+            ParseError::InvalidHost(host) => write!(f, "invalid host: {host}"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses a `host:port` target into its host and port.
+///
+/// IPv6 addresses must be enclosed in brackets when followed by a port, e.g. `[::1]:8080`;
+/// the returned host has no brackets, so it can be used with `(host, port)` as a socket address.
+/// A target without port is rejected with [`ParseError::MissingPort`], see [`parse_target_or`].
+pub fn parse_target(target: &str) -> Result<(String, u16), ParseError> {
+    let (host, port) = split_target(target)?;
+    match port {
+        Some(port) => Ok((host, port)),
+        None => Err(ParseError::MissingPort),
+    }
+}
+
+/// Like [`parse_target`], but uses `default_port` for targets without port, e.g. `localhost`,
+/// `[::1]` or `::1`
+pub fn parse_target_or(target: &str, default_port: u16) -> Result<(String, u16), ParseError> {
+    let (host, port) = split_target(target)?;
+    Ok((host, port.unwrap_or(default_port)))
+}
+
+/// Parses a `host:port` target with [`parse_target`] and attempts a TCP connection to it.
+/// Returns `false` if the target cannot be parsed.
+pub fn is_target_reachable(target: &str, timeout: Duration) -> bool {
+    parse_target(target)
+        .map(|(host, port)| is_port_reachable_with_timeout((host.as_str(), port), timeout))
+        .unwrap_or(false)
+}
+
+fn split_target(target: &str) -> Result<(String, Option<u16>), ParseError> {
+    if target.is_empty() {
+        return Err(ParseError::Empty);
+    }
+    if let Some(bracketed) = target.strip_prefix('[') {
+        let (host, rest) = bracketed
+            .split_once(']')
+            .ok_or_else(|| ParseError::InvalidHost(target.to_string()))?;
+        host.parse::<Ipv6Addr>()
+            .map_err(|_| ParseError::InvalidHost(host.to_string()))?;
+        let port = match rest {
+            "" => None,
+            _ => match rest.strip_prefix(':') {
+                Some(port) => Some(parse_port(port)?),
+                None => return Err(ParseError::InvalidHost(target.to_string())),
+            },
+        };
+        return Ok((host.to_string(), port));
+    }
+    match target.rsplit_once(':') {
+        // More than one colon is only valid for an IPv6 address without port
+        Some((host, _port)) if host.contains(':') => match target.parse::<Ipv6Addr>() {
+            Ok(_) => Ok((target.to_string(), None)),
+            Err(_) => Err(ParseError::InvalidHost(target.to_string())),
+        },
+        Some(("", _port)) => Err(ParseError::MissingHost),
+        Some((host, port)) => Ok((host.to_string(), Some(parse_port(port)?))),
+        None => Ok((target.to_string(), None)),
+    }
+}
+
+fn parse_port(port: &str) -> Result<u16, ParseError> {
+    match port.parse::<u16>() {
+        Ok(number) if number != 0 => Ok(number),
+        _ => Err(ParseError::InvalidPort(port.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serial_test::serial;
+    use std::net::TcpListener;
+
+    #[test]
+    fn should_parse_host_and_port() {
+        assert_eq!(
+            Ok(("localhost".to_string(), 8080)),
+            parse_target("localhost:8080")
+        );
+        assert_eq!(
+            Ok(("127.0.0.1".to_string(), 80)),
+            parse_target("127.0.0.1:80")
+        );
+        assert_eq!(Ok(("::1".to_string(), 8080)), parse_target("[::1]:8080"));
+    }
+
+    #[test]
+    fn should_use_the_default_port_for_bare_hosts() {
+        assert_eq!(
+            Ok(("localhost".to_string(), 80)),
+            parse_target_or("localhost", 80)
+        );
+        assert_eq!(Ok(("::1".to_string(), 80)), parse_target_or("[::1]", 80));
+        assert_eq!(Ok(("::1".to_string(), 80)), parse_target_or("::1", 80));
+        assert_eq!(
+            Ok(("::1".to_string(), 8080)),
+            parse_target_or("[::1]:8080", 80)
+        );
+        assert_eq!(Err(ParseError::MissingPort), parse_target("localhost"));
+    }
+
+    #[test]
+    fn should_reject_malformed_targets() {
+        assert_eq!(Err(ParseError::Empty), parse_target(""));
+        assert_eq!(Err(ParseError::MissingHost), parse_target(":80"));
+        assert_eq!(
+            Err(ParseError::InvalidPort("http".to_string())),
+            parse_target("localhost:http")
+        );
+        assert_eq!(
+            Err(ParseError::InvalidPort("65536".to_string())),
+            parse_target("localhost:65536")
+        );
+        assert_eq!(
+            Err(ParseError::InvalidPort("0".to_string())),
+            parse_target("localhost:0")
+        );
+        assert_eq!(
+            Err(ParseError::InvalidHost("[::1:80".to_string())),
+            parse_target("[::1:80")
+        );
+        assert_eq!(
+            Err(ParseError::InvalidHost("::1:zz".to_string())),
+            parse_target("::1:zz")
+        );
+        assert_eq!(
+            Err(ParseError::InvalidHost("localhost".to_string())),
+            parse_target("[localhost]:80")
+        );
+        assert_eq!(
+            Err(ParseError::InvalidHost("[::1]80".to_string())),
+            parse_target("[::1]80")
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn should_check_the_reachability_of_a_raw_target() {
+        let listener = TcpListener::bind("[::1]:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let timeout = Duration::from_millis(100);
+        assert!(is_target_reachable(&format!("[::1]:{port}"), timeout));
+        assert!(!is_target_reachable("[::1]", timeout));
+
+        drop(listener);
+        assert!(!is_target_reachable(&format!("[::1]:{port}"), timeout));
+    }
+}
@@ -0,0 +1,120 @@
+//! Low level information about established TCP connections.
+
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::reachability::connect_first;
+
+/// Information reported by the OS about an established TCP connection.
+/// Fields are `None` when the platform does not provide them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TcpInfo {
+    /// The maximum segment size of the connection
+    pub mss: Option<u32>,
+    /// The smoothed round trip time estimated by the kernel (Linux only)
+    pub rtt: Option<Duration>,
+    /// The variance of the round trip time estimated by the kernel (Linux only)
+    pub rtt_var: Option<Duration>,
+}
+
+/// Attempts a TCP connection to an address and, if it succeeds, returns the information
+/// the OS reports about the established connection.
+pub fn reachable_with_tcp_info<A: ToSocketAddrs>(address: A, timeout: Duration) -> Option<TcpInfo> {
+    connect_first(address, timeout)
+        .ok()
+        .map(|stream| tcp_info(&stream))
+}
+
+/// Returns the information the OS reports about a TCP connection
+pub(crate) fn tcp_info(stream: &TcpStream) -> TcpInfo {
+    let (rtt, rtt_var) = kernel_rtt(stream).unzip();
+    TcpInfo {
+        mss: mss(stream),
+        rtt,
+        rtt_var,
+    }
+}
+
+#[cfg(unix)]
+fn mss(stream: &TcpStream) -> Option<u32> {
+    socket2::SockRef::from(stream).tcp_mss().ok()
+}
+
+#[cfg(not(unix))]
+fn mss(_stream: &TcpStream) -> Option<u32> {
+    None
+}
+
+/// Returns the round trip time and its variance from `TCP_INFO`
+#[cfg(target_os = "linux")]
+fn kernel_rtt(stream: &TcpStream) -> Option<(Duration, Duration)> {
+    kernel_tcp_info(stream).map(|info| {
+        (
+            Duration::from_micros(u64::from(info.tcpi_rtt)),
+            Duration::from_micros(u64::from(info.tcpi_rttvar)),
+        )
+    })
+}
+
+/// The `tcpi_options` flag reporting that the data sent in the SYN was acknowledged, from `linux/tcp.h`
+#[cfg(target_os = "linux")]
+const TCPI_OPT_SYN_DATA: u8 = 32;
+
+/// Returns whether the data sent in the SYN of a connection with Fast Open was acknowledged by the peer
+#[cfg(target_os = "linux")]
+pub(crate) fn syn_data_acked<S: std::os::fd::AsRawFd>(socket: &S) -> Option<bool> {
+    kernel_tcp_info(socket).map(|info| info.tcpi_options & TCPI_OPT_SYN_DATA != 0)
+}
+
+/// Reads `TCP_INFO` from a TCP socket
+#[cfg(target_os = "linux")]
+fn kernel_tcp_info<S: std::os::fd::AsRawFd>(socket: &S) -> Option<libc::tcp_info> {
+    // SAFETY: tcp_info is a plain C struct for which all zeroes is a valid value
+    let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+    // SAFETY: the pointers reference a live tcp_info and its length, as getsockopt expects
+    let result = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut info as *mut libc::tcp_info as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    (result == 0).then_some(info)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn kernel_rtt(_stream: &TcpStream) -> Option<(Duration, Duration)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serial_test::serial;
+    use std::net::TcpListener;
+
+    #[test]
+    #[serial]
+    fn should_return_tcp_info_of_reachable_addresses_only() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let info = reachable_with_tcp_info(address, Duration::from_millis(100)).unwrap();
+        #[cfg(unix)]
+        assert!(info.mss.is_some());
+        #[cfg(target_os = "linux")]
+        assert!(info.rtt.is_some());
+        #[cfg(not(unix))]
+        assert_eq!(None, info.mss);
+
+        drop(listener);
+        assert_eq!(
+            None,
+            reachable_with_tcp_info(address, Duration::from_millis(100))
+        );
+    }
+}
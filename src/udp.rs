@@ -0,0 +1,216 @@
+//! Reachability checks of UDP services.
+
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+use crate::is_port_reachable_with_timeout;
+use crate::reachability::Deadline;
+
+/// The largest UDP payload
+const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+/// Sends a request datagram to a UDP service and returns whether a response accepted by
+/// `matches` arrives within the timeout.
+///
+/// UDP has no handshake, so a service can only be considered reachable if it answers:
+/// responses rejected by `matches` are ignored and waiting continues until the timeout.
+/// When the address resolves to several socket addresses, they are tried in order,
+/// each one with its own timeout.
+/// An ICMP "port unreachable" reported by the OS ends the attempt early.
+pub fn is_udp_service_reachable<A, F>(
+    address: A,
+    request: &[u8],
+    matches: F,
+    timeout: Duration,
+) -> bool
+where
+    A: ToSocketAddrs,
+    F: Fn(&[u8]) -> bool,
+{
+    match address.to_socket_addrs() {
+        Ok(addrs) => addrs
+            .into_iter()
+            .any(|address| exchange(address, request, &matches, timeout).unwrap_or(false)),
+        Err(_err) => false,
+    }
+}
+
+/// Checks a service listening on the same port over both TCP and UDP, e.g. a DNS server,
+/// and returns whether it is reachable over TCP and over UDP, in this order.
+///
+/// The address is resolved once, then the two checks run concurrently, so the call takes about
+/// `timeout` per resolved address at most. TCP is checked with a connection attempt as
+/// [`is_port_reachable_with_timeout`] does, UDP by sending `udp_probe` and waiting for any
+/// response as [`is_udp_service_reachable`] does.
+pub fn is_port_reachable_tcp_and_udp<A: ToSocketAddrs>(
+    address: A,
+    udp_probe: &[u8],
+    timeout: Duration,
+) -> (bool, bool) {
+    let addrs: Vec<SocketAddr> = match address.to_socket_addrs() {
+        Ok(addrs) => addrs.collect(),
+        Err(_err) => return (false, false),
+    };
+    thread::scope(|scope| {
+        let tcp = scope.spawn(|| is_port_reachable_with_timeout(&addrs[..], timeout));
+        let udp = is_udp_service_reachable(&addrs[..], udp_probe, |_response| true, timeout);
+        (tcp.join().unwrap_or(false), udp)
+    })
+}
+
+/// Sends a probe datagram on a connected UDP socket and returns whether the peer answers
+/// within the timeout, so a warm socket can be reused for repeated liveness checks,
+/// as [`is_stream_alive`](crate::is_stream_alive) does for TCP.
+///
+/// Any datagram received counts as an answer, so responses to earlier probes that arrive late
+/// are taken as a sign of life. An ICMP "port unreachable" reported by the OS means the peer is down.
+/// UDP liveness is inherently unreliable: a lost probe or response, a firewall dropping the
+/// ICMP errors, or a service ignoring the probe are all indistinguishable from a dead peer
+/// until the timeout. The read timeout of the socket is restored before returning.
+pub fn is_udp_peer_alive(socket: &UdpSocket, probe: &[u8], timeout: Duration) -> bool {
+    let Ok(previous) = socket.read_timeout() else {
+        return false;
+    };
+    let alive = probe_peer(socket, probe, timeout).unwrap_or(false);
+    socket.set_read_timeout(previous).is_ok() && alive
+}
+
+fn probe_peer(socket: &UdpSocket, probe: &[u8], timeout: Duration) -> io::Result<bool> {
+    if timeout.is_zero() {
+        return Ok(false);
+    }
+    socket.send(probe)?;
+    socket.set_read_timeout(Some(timeout))?;
+    let mut response = vec![0u8; MAX_DATAGRAM_SIZE];
+    socket.recv(&mut response)?;
+    Ok(true)
+}
+
+fn exchange<F: Fn(&[u8]) -> bool>(
+    address: SocketAddr,
+    request: &[u8],
+    matches: &F,
+    timeout: Duration,
+) -> io::Result<bool> {
+    let deadline = Deadline::after(timeout);
+    let socket = match address {
+        SocketAddr::V4(_) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?,
+        SocketAddr::V6(_) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?,
+    };
+    // A connected socket only receives the datagrams sent by the service
+    socket.connect(address)?;
+    socket.send(request)?;
+
+    let mut response = vec![0u8; MAX_DATAGRAM_SIZE];
+    loop {
+        let remaining = deadline.remaining();
+        if remaining.is_zero() {
+            return Ok(false);
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let received = socket.recv(&mut response)?;
+        if matches(&response[..received]) {
+            return Ok(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serial_test::serial;
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    /// Starts a service answering a single request with `noise` first, then with `response`
+    fn start_udp_service(
+        noise: &'static [u8],
+        response: &'static [u8],
+    ) -> (SocketAddr, thread::JoinHandle<()>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut request = [0u8; 64];
+            let (_received, client) = socket.recv_from(&mut request).unwrap();
+            socket.send_to(noise, client).unwrap();
+            socket.send_to(response, client).unwrap();
+        });
+        (address, handle)
+    }
+
+    #[test]
+    #[serial]
+    fn should_be_reachable_if_a_matching_response_arrives() {
+        let (address, handle) = start_udp_service(b"noise", b"pong");
+        assert!(is_udp_service_reachable(
+            address,
+            b"ping",
+            |response| response == b"pong",
+            Duration::from_secs(1)
+        ));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn should_not_be_reachable_without_a_matching_response() {
+        let (address, handle) = start_udp_service(b"noise", b"pong");
+        let timeout = Duration::from_millis(200);
+        let start = Instant::now();
+        assert!(!is_udp_service_reachable(
+            address,
+            b"ping",
+            |response| response == b"other",
+            timeout
+        ));
+        assert!(start.elapsed() >= timeout);
+        handle.join().unwrap();
+
+        // Nobody is listening anymore
+        assert!(!is_udp_service_reachable(
+            address,
+            b"ping",
+            |_response| true,
+            timeout
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn a_peer_should_be_alive_while_it_answers() {
+        let (address, handle) = start_udp_service(b"noise", b"pong");
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(address).unwrap();
+        assert!(is_udp_peer_alive(&socket, b"ping", Duration::from_secs(1)));
+        assert_eq!(None, socket.read_timeout().unwrap());
+        handle.join().unwrap();
+        // Only the noise was consumed by the probe
+        let mut response = [0u8; 4];
+        assert_eq!(4, socket.recv(&mut response).unwrap());
+
+        // Nobody is listening anymore
+        let timeout = Duration::from_millis(200);
+        assert!(!is_udp_peer_alive(&socket, b"ping", timeout));
+    }
+
+    #[test]
+    #[serial]
+    fn should_report_the_reachability_of_each_protocol() {
+        let (address, handle) = start_udp_service(b"noise", b"pong");
+        let timeout = Duration::from_millis(200);
+        assert_eq!(
+            (false, true),
+            is_port_reachable_tcp_and_udp(address, b"ping", timeout)
+        );
+        handle.join().unwrap();
+
+        let _listener = TcpListener::bind(address).unwrap();
+        assert_eq!(
+            (true, false),
+            is_port_reachable_tcp_and_udp(address, b"ping", timeout)
+        );
+    }
+}
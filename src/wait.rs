@@ -0,0 +1,364 @@
+use std::fmt::Debug;
+use std::net::ToSocketAddrs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::reachability::{connect_any, Deadline, Reachability};
+use crate::{is_local_port_free, Port};
+
+/// The initial delay between two attempts used by [`wait_until_reachable`]
+const DEFAULT_INITIAL_DELAY: Duration = Duration::from_millis(10);
+/// The maximum delay between two attempts used by [`wait_until_reachable`]
+const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(1);
+/// The number of consecutive resolution failures after which waiting stops
+const MAX_RESOLUTION_FAILURES: u32 = 3;
+
+/// The outcome of waiting for an address to become reachable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitOutcome {
+    /// Whether the address became reachable before the timeout
+    pub reachable: bool,
+    /// The number of connection attempts made
+    pub attempts: u32,
+    /// The time spent waiting
+    pub elapsed: Duration,
+    /// Whether waiting stopped before the timeout because the address repeatedly failed to resolve
+    pub unresolvable: bool,
+}
+
+/// Waits until a TCP connection to an address succeeds or the timeout elapses, whichever comes first.
+/// The delay between two attempts starts at `initial_delay` and doubles after each failure, up to `max_delay`.
+///
+/// The address is resolved again at each attempt; if resolution fails three times in a row,
+/// waiting stops early and the outcome is marked as `unresolvable`, as this usually means the
+/// host name is wrong rather than that the service is still starting.
+pub fn wait_until_reachable_backoff<A: ToSocketAddrs>(
+    address: A,
+    timeout: Duration,
+    initial_delay: Duration,
+    max_delay: Duration,
+) -> WaitOutcome {
+    backoff(address, timeout, initial_delay, max_delay).0
+}
+
+/// The loop of [`wait_until_reachable_backoff`], also returning the outcome of the last attempt
+fn backoff<A: ToSocketAddrs>(
+    address: A,
+    timeout: Duration,
+    initial_delay: Duration,
+    max_delay: Duration,
+) -> (WaitOutcome, Reachability) {
+    let start = Instant::now();
+    let deadline = Deadline::after(timeout);
+    let mut delay = initial_delay;
+    let mut attempts = 0;
+    let mut resolution_failures = 0;
+    let mut last = Reachability::Unreachable;
+
+    loop {
+        attempts += 1;
+        let reachable = match address.to_socket_addrs() {
+            Ok(addrs) => {
+                resolution_failures = 0;
+                let remaining = deadline.remaining();
+                !remaining.is_zero()
+                    && match connect_any(addrs, remaining) {
+                        Ok(_connected) => true,
+                        Err(reachability) => {
+                            last = reachability;
+                            false
+                        }
+                    }
+            }
+            Err(_err) => {
+                resolution_failures += 1;
+                last = Reachability::Unreachable;
+                false
+            }
+        };
+        let unresolvable = resolution_failures >= MAX_RESOLUTION_FAILURES;
+        let remaining = deadline.remaining();
+        if reachable || unresolvable || remaining.is_zero() {
+            let outcome = WaitOutcome {
+                reachable,
+                attempts,
+                elapsed: start.elapsed(),
+                unresolvable,
+            };
+            return (outcome, if reachable { Reachability::Open } else { last });
+        }
+        thread::sleep(delay.min(remaining));
+        delay = (delay * 2).min(max_delay);
+    }
+}
+
+/// Waits until a TCP connection to an address succeeds or the timeout elapses and returns whether it succeeded.
+/// Attempts are retried with an exponential backoff from 10 milliseconds up to 1 second.
+pub fn wait_until_reachable<A: ToSocketAddrs>(address: A, timeout: Duration) -> bool {
+    wait_until_reachable_backoff(address, timeout, DEFAULT_INITIAL_DELAY, DEFAULT_MAX_DELAY)
+        .reachable
+}
+
+/// Waits until a TCP connection to every target succeeds or the timeout elapses.
+///
+/// The timeout is a single deadline for all the targets, which are waited for concurrently,
+/// one thread each, as [`wait_until_reachable`] does: the call returns as soon as all the
+/// targets are reachable, and after about `timeout` otherwise, however many targets there are.
+/// On timeout, the targets that are still not reachable are returned, in the original order.
+pub fn wait_until_all_reachable(
+    targets: &[(String, u16)],
+    timeout: Duration,
+) -> Result<(), Vec<(String, u16)>> {
+    let down: Vec<(String, u16)> = thread::scope(|scope| {
+        let waits: Vec<_> = targets
+            .iter()
+            .map(|(host, port)| {
+                scope.spawn(move || wait_until_reachable((host.as_str(), *port), timeout))
+            })
+            .collect();
+        let reachable = waits.into_iter().map(|wait| wait.join().unwrap_or(false));
+        targets
+            .iter()
+            .zip(reachable)
+            .filter(|(_target, reachable)| !reachable)
+            .map(|(target, _reachable)| target.clone())
+            .collect()
+    });
+    if down.is_empty() {
+        Ok(())
+    } else {
+        Err(down)
+    }
+}
+
+/// Test helper waiting until a TCP connection to an address succeeds, like [`wait_until_reachable`],
+/// and panicking if it does not succeed within the timeout.
+///
+/// The panic message reports the address, the time spent waiting and the outcome of the last attempt,
+/// e.g. whether the connection was refused or timed out. As with [`wait_until_reachable_backoff`],
+/// an address that repeatedly fails to resolve panics early, reporting that it could not be resolved.
+#[track_caller]
+pub fn assert_reachable_within<A: ToSocketAddrs + Debug>(address: A, timeout: Duration) {
+    let (outcome, last) = backoff(&address, timeout, DEFAULT_INITIAL_DELAY, DEFAULT_MAX_DELAY);
+    if outcome.unresolvable {
+        panic!(
+            "{address:?} could not be resolved: gave up after {} attempts in {:?}",
+            outcome.attempts, outcome.elapsed
+        );
+    }
+    if !outcome.reachable {
+        panic!(
+            "{address:?} was not reachable within {timeout:?}: waited {:?}, the last attempt was {last:?}",
+            outcome.elapsed
+        );
+    }
+}
+
+/// Like [`wait_until_reachable`], with the timeout in seconds
+pub fn wait_until_reachable_secs<A: ToSocketAddrs>(address: A, secs: u64) -> bool {
+    wait_until_reachable(address, Duration::from_secs(secs))
+}
+
+/// Waits until a localhost port is no longer available, e.g. because a server just bound it,
+/// checking every `poll_interval` until the timeout elapses. Returns whether the port became busy.
+/// If the IP version is not specified, it defaults to IPv4. This happens when the port is specified as a number.
+/// A [`Port::Both`] is busy as soon as it is no longer available for one of the IP versions.
+pub fn wait_until_local_port_busy<P: Into<Port>>(
+    port: P,
+    timeout: Duration,
+    poll_interval: Duration,
+) -> bool {
+    let port = port.into();
+    let deadline = Deadline::after(timeout);
+    loop {
+        if !is_local_port_free(port) {
+            return true;
+        }
+        let remaining = deadline.remaining();
+        if remaining.is_zero() {
+            return false;
+        }
+        thread::sleep(poll_interval.min(remaining));
+    }
+}
+
+/// Waits until a localhost port is no longer available for IPv4. See [`wait_until_local_port_busy`].
+pub fn wait_until_local_ipv4_port_busy(
+    port: u16,
+    timeout: Duration,
+    poll_interval: Duration,
+) -> bool {
+    wait_until_local_port_busy(Port::ipv4(port), timeout, poll_interval)
+}
+
+/// Waits until a localhost port is no longer available for IPv6. See [`wait_until_local_port_busy`].
+pub fn wait_until_local_ipv6_port_busy(
+    port: u16,
+    timeout: Duration,
+    poll_interval: Duration,
+) -> bool {
+    wait_until_local_port_busy(Port::ipv6(port), timeout, poll_interval)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serial_test::serial;
+    use std::net::TcpListener;
+
+    #[test]
+    #[serial]
+    fn should_stop_waiting_as_soon_as_the_address_is_reachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let outcome = wait_until_reachable_backoff(
+            address,
+            Duration::from_secs(1),
+            Duration::from_millis(10),
+            Duration::from_millis(100),
+        );
+        assert!(outcome.reachable);
+        assert_eq!(1, outcome.attempts);
+        assert!(wait_until_reachable(address, Duration::from_secs(1)));
+    }
+
+    #[test]
+    #[serial]
+    fn should_report_the_attempts_made_before_the_timeout() {
+        let port = crate::free_local_ipv4_port().unwrap();
+        let timeout = Duration::from_millis(200);
+
+        let outcome = wait_until_reachable_backoff(
+            ("127.0.0.1", port),
+            timeout,
+            Duration::from_millis(10),
+            Duration::from_millis(50),
+        );
+        assert!(!outcome.reachable);
+        assert!(!outcome.unresolvable);
+        assert!(outcome.attempts > 1);
+        assert!(outcome.elapsed >= timeout);
+        assert!(outcome.elapsed < 2 * timeout);
+    }
+
+    #[test]
+    #[serial]
+    fn should_wait_for_all_the_targets_within_a_single_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let up = (
+            "127.0.0.1".to_string(),
+            listener.local_addr().unwrap().port(),
+        );
+        let down = (
+            "127.0.0.1".to_string(),
+            crate::free_local_ipv4_port().unwrap(),
+        );
+        let timeout = Duration::from_millis(200);
+
+        let start = Instant::now();
+        let targets = [down.clone(), up.clone(), down.clone(), down.clone()];
+        assert_eq!(
+            Err(vec![down.clone(), down.clone(), down]),
+            wait_until_all_reachable(&targets, timeout)
+        );
+        assert!(start.elapsed() < 2 * timeout);
+
+        let start = Instant::now();
+        assert_eq!(Ok(()), wait_until_all_reachable(&[up], timeout));
+        assert!(start.elapsed() < timeout);
+    }
+
+    #[test]
+    #[serial]
+    fn should_not_panic_if_the_address_is_reachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        assert_reachable_within(listener.local_addr().unwrap(), Duration::from_secs(1));
+    }
+
+    #[test]
+    #[serial]
+    fn should_not_panic_with_a_timeout_too_large_for_a_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        assert!(
+            wait_until_reachable_backoff(
+                address,
+                Duration::MAX,
+                Duration::from_millis(10),
+                Duration::from_millis(10)
+            )
+            .reachable
+        );
+        assert_reachable_within(address, Duration::MAX);
+        assert!(wait_until_local_ipv4_port_busy(
+            address.port(),
+            Duration::MAX,
+            Duration::from_millis(10)
+        ));
+    }
+
+    #[test]
+    #[serial]
+    #[should_panic(expected = "was not reachable within 100ms")]
+    fn should_panic_with_the_last_outcome_if_the_address_is_not_reachable() {
+        let port = crate::free_local_ipv4_port().unwrap();
+        assert_reachable_within(("127.0.0.1", port), Duration::from_millis(100));
+    }
+
+    #[test]
+    #[should_panic(expected = "could not be resolved")]
+    fn should_panic_early_if_the_address_does_not_resolve() {
+        assert_reachable_within("no port to resolve", Duration::from_secs(60));
+    }
+
+    #[test]
+    fn should_stop_waiting_if_the_address_does_not_resolve() {
+        let timeout = Duration::from_secs(10);
+        let outcome = wait_until_reachable_backoff(
+            "no port to resolve",
+            timeout,
+            Duration::from_millis(1),
+            Duration::from_millis(1),
+        );
+        assert!(!outcome.reachable);
+        assert!(outcome.unresolvable);
+        assert_eq!(MAX_RESOLUTION_FAILURES, outcome.attempts);
+        assert!(outcome.elapsed < timeout);
+    }
+
+    #[test]
+    #[serial]
+    fn should_wait_until_a_port_is_bound() {
+        let port = crate::free_local_ipv6_port().unwrap();
+        let server = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            let _listener = TcpListener::bind(("::1", port)).unwrap();
+            thread::sleep(Duration::from_millis(500));
+        });
+
+        assert!(wait_until_local_ipv6_port_busy(
+            port,
+            Duration::from_secs(2),
+            Duration::from_millis(10)
+        ));
+        server.join().unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn should_stop_waiting_for_a_free_port_at_the_timeout() {
+        let port = crate::free_local_ipv4_port().unwrap();
+        let timeout = Duration::from_millis(100);
+        let start = Instant::now();
+
+        assert!(!wait_until_local_ipv4_port_busy(
+            port,
+            timeout,
+            Duration::from_millis(10)
+        ));
+        assert!(start.elapsed() >= timeout);
+    }
+}
@@ -0,0 +1,92 @@
+//! Repeated liveness checks reusing an established connection.
+
+use std::io;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::reachability::connect_any;
+use crate::{is_stream_alive, Reachability};
+
+/// A liveness probe for a single endpoint that keeps its connection open between checks,
+/// for tight monitoring loops where opening a connection at each check would be wasteful.
+///
+/// Each [`WarmProbe::check`] first looks at the connection kept from the previous check:
+/// while the OS reports it as open, no new connection is made. Once the peer closes or resets it,
+/// or if there is none yet, a new connection is attempted to the addresses resolved at creation.
+///
+/// This is best-effort liveness: a kept connection only proves that the peer has not closed it,
+/// so a host that vanished without closing its connections is only detected once the OS gives up
+/// on the connection.
+#[derive(Debug)]
+pub struct WarmProbe {
+    addrs: Vec<SocketAddr>,
+    timeout: Duration,
+    stream: Option<TcpStream>,
+}
+
+impl WarmProbe {
+    /// Creates a probe for an address with a timeout for each connection attempt.
+    /// The address is resolved once, here. No connection is made until the first check.
+    pub fn new<A: ToSocketAddrs>(address: A, timeout: Duration) -> io::Result<Self> {
+        let addrs: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+        if addrs.is_empty() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        Ok(WarmProbe {
+            addrs,
+            timeout,
+            stream: None,
+        })
+    }
+
+    /// Returns whether the endpoint is reachable, reusing the kept connection while it is open,
+    /// otherwise reconnecting
+    pub fn check(&mut self) -> Reachability {
+        if self.stream.as_ref().is_some_and(is_stream_alive) {
+            return Reachability::Open;
+        }
+        self.stream = None;
+        match connect_any(self.addrs.iter().copied(), self.timeout) {
+            Ok((stream, _address)) => {
+                self.stream = Some(stream);
+                Reachability::Open
+            }
+            Err(reachability) => reachability,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serial_test::serial;
+    use std::net::TcpListener;
+
+    #[test]
+    #[serial]
+    fn should_reuse_the_connection_while_it_is_open() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut probe =
+            WarmProbe::new(listener.local_addr().unwrap(), Duration::from_millis(100)).unwrap();
+
+        assert_eq!(Reachability::Open, probe.check());
+        assert_eq!(Reachability::Open, probe.check());
+        let (server, _) = listener.accept().unwrap();
+        listener.set_nonblocking(true).unwrap();
+        assert_eq!(
+            io::ErrorKind::WouldBlock,
+            listener.accept().unwrap_err().kind()
+        );
+
+        drop(server);
+        drop(listener);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(Reachability::Refused, probe.check());
+    }
+
+    #[test]
+    fn should_fail_to_create_a_probe_for_an_unresolvable_address() {
+        assert!(WarmProbe::new("missing port", Duration::from_millis(100)).is_err());
+    }
+}
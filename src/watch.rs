@@ -0,0 +1,93 @@
+//! Background monitoring of the reachability of an address.
+
+use std::net::ToSocketAddrs;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::{port_reachability, Reachability};
+
+/// A running reachability watch started by [`watch_reachability`].
+/// The watch is stopped when the handle is dropped.
+#[derive(Debug)]
+pub struct WatchHandle {
+    stop: Option<mpsc::Sender<()>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl WatchHandle {
+    /// Stops the watch, waiting for the probe in progress, if any, to complete.
+    /// The callback is never invoked after this returns.
+    pub fn stop(self) {}
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        // Dropping the sender wakes up the watching thread
+        drop(self.stop.take());
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Attempts a TCP connection to an address every `interval` from a background thread, and invokes
+/// `on_change` with the outcome each time it differs from the previous one.
+///
+/// The callback is also invoked with the outcome of the first attempt, so the initial state is known.
+/// Each attempt uses `interval` as its connection timeout, and the address is resolved again on
+/// each attempt, so a host name that moves to another IP address is followed.
+pub fn watch_reachability<A, F>(address: A, interval: Duration, mut on_change: F) -> WatchHandle
+where
+    A: ToSocketAddrs + Send + 'static,
+    F: FnMut(Reachability) + Send + 'static,
+{
+    let (stop, stopped) = mpsc::channel::<()>();
+    let worker = thread::spawn(move || {
+        let mut last = None;
+        loop {
+            let reachability = port_reachability(&address, interval);
+            if last != Some(reachability) {
+                last = Some(reachability);
+                on_change(reachability);
+            }
+            match stopped.recv_timeout(interval) {
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                _ => return,
+            }
+        }
+    });
+    WatchHandle {
+        stop: Some(stop),
+        worker: Some(worker),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serial_test::serial;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    #[serial]
+    fn should_report_only_the_state_changes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let changes = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = changes.clone();
+        let watch = watch_reachability(address, Duration::from_millis(20), move |reachability| {
+            recorded.lock().unwrap().push(reachability)
+        });
+        thread::sleep(Duration::from_millis(150));
+        drop(listener);
+        thread::sleep(Duration::from_millis(150));
+        watch.stop();
+
+        let changes = changes.lock().unwrap().clone();
+        assert_eq!(vec![Reachability::Open, Reachability::Refused], changes);
+    }
+}
@@ -0,0 +1,100 @@
+//! Well-known ports of common services, e.g. `is_port_reachable(("example.com", well_known::HTTPS))`.
+//!
+//! The list is not exhaustive: it only covers widely used services, with their default TCP port.
+
+/// File Transfer Protocol (control connection)
+pub const FTP: u16 = 21;
+/// Secure Shell
+pub const SSH: u16 = 22;
+/// Telnet
+pub const TELNET: u16 = 23;
+/// Simple Mail Transfer Protocol
+pub const SMTP: u16 = 25;
+/// Domain Name System
+pub const DNS: u16 = 53;
+/// Hypertext Transfer Protocol
+pub const HTTP: u16 = 80;
+/// Post Office Protocol version 3
+pub const POP3: u16 = 110;
+/// Internet Message Access Protocol
+pub const IMAP: u16 = 143;
+/// Lightweight Directory Access Protocol
+pub const LDAP: u16 = 389;
+/// HTTP over TLS
+pub const HTTPS: u16 = 443;
+/// SMTP over TLS
+pub const SMTPS: u16 = 465;
+/// Mail submission
+pub const SUBMISSION: u16 = 587;
+/// LDAP over TLS
+pub const LDAPS: u16 = 636;
+/// IMAP over TLS
+pub const IMAPS: u16 = 993;
+/// POP3 over TLS
+pub const POP3S: u16 = 995;
+/// Microsoft SQL Server
+pub const MSSQL: u16 = 1433;
+/// MQTT
+pub const MQTT: u16 = 1883;
+/// MySQL and MariaDB
+pub const MYSQL: u16 = 3306;
+/// Remote Desktop Protocol
+pub const RDP: u16 = 3389;
+/// PostgreSQL
+pub const POSTGRES: u16 = 5432;
+/// Advanced Message Queuing Protocol, e.g. RabbitMQ
+pub const AMQP: u16 = 5672;
+/// Redis
+pub const REDIS: u16 = 6379;
+/// The usual alternative HTTP port
+pub const HTTP_ALT: u16 = 8080;
+/// MongoDB
+pub const MONGODB: u16 = 27017;
+
+/// Returns the name of the service usually listening on a port, e.g. to annotate the output
+/// of a scan, or `None` if the port is not in the list of this module
+pub fn service_name(port: u16) -> Option<&'static str> {
+    let name = match port {
+        FTP => "ftp",
+        SSH => "ssh",
+        TELNET => "telnet",
+        SMTP => "smtp",
+        DNS => "dns",
+        HTTP => "http",
+        POP3 => "pop3",
+        IMAP => "imap",
+        LDAP => "ldap",
+        HTTPS => "https",
+        SMTPS => "smtps",
+        SUBMISSION => "submission",
+        LDAPS => "ldaps",
+        IMAPS => "imaps",
+        POP3S => "pop3s",
+        MSSQL => "mssql",
+        MQTT => "mqtt",
+        MYSQL => "mysql",
+        RDP => "rdp",
+        POSTGRES => "postgres",
+        AMQP => "amqp",
+        REDIS => "redis",
+        HTTP_ALT => "http-alt",
+        MONGODB => "mongodb",
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn should_name_the_well_known_ports() {
+        assert_eq!(Some("https"), service_name(HTTPS));
+        assert_eq!(Some("ssh"), service_name(22));
+        assert_eq!(Some("postgres"), service_name(5432));
+        assert_eq!(None, service_name(0));
+        assert_eq!(None, service_name(49152));
+    }
+}